@@ -0,0 +1,39 @@
+//! Check scene files for mistakes, without starting the game
+//!
+//! Usage: `cargo run --bin bevyjam-lint -- assets/path/to/file.scn.ron ...`
+//!
+//! Exits with a non-zero status if any problems are found, so it can be
+//! used from CI or a pre-commit hook.
+
+use std::path::Path;
+use std::process::exit;
+
+use bevyjam::lint::lint_scene_file;
+
+fn main() {
+    let files: Vec<String> = std::env::args().skip(1).collect();
+    if files.is_empty() {
+        eprintln!("Usage: bevyjam-lint <file.scn.ron>...");
+        exit(2);
+    }
+
+    let mut failed = false;
+    for file in files.iter() {
+        match lint_scene_file(Path::new(file)) {
+            Ok(problems) => {
+                for problem in problems.iter() {
+                    println!("{}: {}", file, problem);
+                }
+                failed |= !problems.is_empty();
+            }
+            Err(e) => {
+                println!("{}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+}
@@ -1,16 +1,28 @@
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
 use enum_iterator::IntoEnumIterator;
-use iyes_bevy_util::{despawn_with_recursive, despawn_with, remove_from_all};
+use iyes_bevy_util::{despawn_with_recursive, despawn_with, remove_from_all, remove_resource};
 
-use crate::{AppState, FuckStages, ui::button_connector, game::blueprints::{Medkit, AmmoBox}};
-use crate::game::collider as gamecollider;
+use crate::{AppState, FuckStages, ui::button_connector, scene_exporter::has_event};
 
 use self::collider::DragHandle;
 
 mod ui;
+mod clipboard;
+mod files;
+mod inspector;
+mod history;
+mod layers;
+mod link;
+mod outliner;
+mod playtest;
+mod prefabs;
+mod textinput;
 
 mod select;
+mod snap;
 mod transform;
+pub mod camera;
 pub mod collider;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
@@ -20,6 +32,7 @@ pub enum UsingTool {
     Move,
     Rotate,
     EditCollider,
+    Link,
 }
 
 #[derive(Component)]
@@ -36,10 +49,38 @@ pub struct DevEditorPlugin;
 impl Plugin for DevEditorPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<select::Selections>();
+        app.init_resource::<select::ActiveSelection>();
+        app.init_resource::<select::Marquee>();
         app.init_resource::<ui::SpawnBtnParent>();
         app.init_resource::<collider::ActiveDraghandle>();
+        app.init_resource::<files::FileListParent>();
+        app.init_resource::<prefabs::PrefabListParent>();
+        app.init_resource::<inspector::Inspector>();
+        app.init_resource::<clipboard::EditorClipboard>();
+        app.init_resource::<inspector::InspectorListParent>();
+        app.init_resource::<layers::LayerListParent>();
+        app.init_resource::<outliner::Outliner>();
+        app.init_resource::<outliner::OutlinerListParent>();
+        app.init_resource::<link::LinkSource>();
+        app.world.get_resource_or_insert_with(layers::EditorLayers::default)
+            .add(crate::scene_exporter::prefab::PREFAB_KIND);
+        // the blueprints may be registered after us
+        app.add_startup_system(layers::add_blueprint_layers);
+        app.init_resource::<textinput::TextInput>();
+        app.init_resource::<history::EditorHistory>();
+        app.init_resource::<history::DragStart>();
+        app.init_resource::<snap::SnapSettings>();
+        app.init_resource::<transform::ToolDragStart>();
+        app.init_resource::<camera::EditorCameraTarget>();
+        app.add_event::<textinput::TextInputSubmit>();
+        app.add_event::<history::HistoryEvent>();
+        app.add_event::<history::DespawnEntitiesEvent>();
+        app.add_event::<inspector::InspectorEditEvent>();
+        app.add_event::<clipboard::ClipboardEvent>();
+        app.add_event::<playtest::PlayFromHereEvent>();
         app.insert_resource(UsingTool::Select);
         app.add_system(enter_exit_editor);
+        app.add_system_to_stage(FuckStages::Pre, textinput::text_input_keyboard);
         app.add_system_set_to_stage(
             CoreStage::PostUpdate,
             SystemSet::new()
@@ -51,7 +92,15 @@ impl Plugin for DevEditorPlugin {
         );
         app.add_system_set(
             SystemSet::on_enter(AppState::DevEditor)
+                .with_system(playtest::end_playtest.exclusive_system())
                 .with_system(ui::spawn_ui.label("editorui"))
+                .with_system(ui::add_spawn_buttons.after("editorui"))
+                .with_system(files::spawn_files_ui)
+                .with_system(inspector::spawn_inspector_ui)
+                .with_system(layers::spawn_layer_ui)
+                .with_system(outliner::spawn_outliner_ui)
+                .with_system(camera::init_camera_target)
+                .with_system(layers::set_hidden_visibility::<false>)
                 .with_system(remove_from_all::<NewlySpawned>)
                 .with_system(select::set_selection_visibility::<true>)
         );
@@ -60,16 +109,63 @@ impl Plugin for DevEditorPlugin {
                 .with_system(despawn_with_recursive::<EditorHideCleanup>)
                 .with_system(select::set_selection_visibility::<false>)
                 .with_system(collider::cleanup_collider_visualizations)
+                .with_system(files::cleanup_files_ui)
+                .with_system(prefabs::cleanup_prefab_ui)
+                .with_system(inspector::cleanup_inspector_ui)
+                .with_system(layers::cleanup_layer_ui)
+                .with_system(outliner::cleanup_outliner_ui)
+                .with_system(camera::reset_camera_target)
+                .with_system(layers::set_hidden_visibility::<true>)
+                .with_system(textinput::cancel_text_input)
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            history::apply_despawns
+                .exclusive_system()
+                .with_run_criteria(has_event::<history::DespawnEntitiesEvent>)
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            history::apply_history
+                .exclusive_system()
+                .with_run_criteria(has_event::<history::HistoryEvent>)
+        );
+        app.add_system_to_stage(FuckStages::Post, camera::init_camera_bookmarks);
+        app.add_system_to_stage(FuckStages::Post, prefabs::init_prefab_instances);
+        app.add_system_to_stage(FuckStages::Post,
+            history::record_spawns
+                .with_run_criteria(in_editor)
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            clipboard::apply_clipboard
+                .exclusive_system()
+                .with_run_criteria(has_event::<clipboard::ClipboardEvent>)
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            playtest::begin_playtest
+                .exclusive_system()
+                .with_run_criteria(has_event::<playtest::PlayFromHereEvent>)
+        );
+        app.add_system_set(
+            SystemSet::on_enter(AppState::MainMenu)
+                .with_system(remove_resource::<playtest::PlaytestSnapshot>)
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            inspector::apply_inspector_edits
+                .exclusive_system()
+                .with_run_criteria(has_event::<inspector::InspectorEditEvent>)
         );
         app.add_stage_after(CoreStage::Update, ToolStage, SystemStage::single_threaded());
         app.add_state_to_stage(ToolStage, ToolState::Inactive);
+        app.add_system_to_stage(ToolStage, history::clear_history_on_scene_change);
         app.add_system_set_to_stage(
             ToolStage,
             SystemSet::on_update(ToolState::Using(UsingTool::Select))
                 .with_system(select::mouse_select)
                 .with_system(select::keyboard_despawn_selected)
                 .with_system(select::keyboard_deselect_all)
-                .with_system(select::keyboard_duplicate_collider)
+                .with_system(select::keyboard_select_same_kind)
+                .with_system(collider::keyboard_cycle_collider_shape)
+                .with_system(select::keyboard_parent_selected)
+                .with_system(prefabs::keyboard_toggle_prefab_link)
         );
         app.add_system_set_to_stage(
             ToolStage,
@@ -97,6 +193,16 @@ impl Plugin for DevEditorPlugin {
             SystemSet::on_exit(ToolState::Using(UsingTool::EditCollider))
                 .with_system(despawn_with::<DragHandle>)
         );
+        app.add_system_set_to_stage(
+            ToolStage,
+            SystemSet::on_update(ToolState::Using(UsingTool::Link))
+                .with_system(link::mouse_link)
+        );
+        app.add_system_set_to_stage(
+            ToolStage,
+            SystemSet::on_exit(ToolState::Using(UsingTool::Link))
+                .with_system(link::clear_link_source)
+        );
         app.add_system_set_to_stage(
             ToolStage,
             SystemSet::on_update(ToolState::Spawning)
@@ -106,28 +212,45 @@ impl Plugin for DevEditorPlugin {
             SystemSet::on_update(AppState::DevEditor)
                 .with_system(ui::tool_btn_visual)
                 .with_system(tool_hotkeys)
+                .with_system(history::history_hotkeys)
+                .with_system(history::record_drags)
+                .with_system(clipboard::clipboard_hotkeys)
+                .with_system(playtest::play_from_here_hotkey)
+                .with_system(select::select_pending)
+                .with_system(snap::snap_hotkeys)
+                .with_system(snap::draw_grid)
                 .with_system(collider::visualize_spriteless_colliders)
+                .with_system(collider::visualize_editor_icons)
+                .with_system(collider::visualize_shaped_colliders)
                 .with_system(collider::update_collider_visualization)
-                .with_system(transform::editor_camera)
-                .with_system(transform::editor_camera_zoom)
+                .with_system(link::draw_links)
+                .with_system(camera::editor_camera)
+                .with_system(camera::editor_camera_zoom)
+                .with_system(camera::editor_camera_smooth)
+                .with_system(camera::keyboard_frame)
+                .with_system(camera::keyboard_camera_bookmarks)
                 .with_system(button_connector::<ui::ToolBtn>.chain(ui::tool_btn_handler))
-                // handle spawn buttons for blueprints:
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<gamecollider::Wall>))
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<gamecollider::HurtZone>))
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<gamecollider::SpawnZone>))
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<gamecollider::WinZone>))
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<Medkit>))
-                .with_system(button_connector.chain(ui::spawn_btn_handler::<AmmoBox>))
+                .with_system(button_connector::<ui::SpawnBtn>.chain(ui::spawn_btn_handler))
+                .with_system(button_connector::<files::FileBtn>.chain(files::file_btn_handler))
+                .with_system(files::save_as_submit)
+                .with_system(files::update_current_file_text)
+                .with_system(files::refresh_file_list)
+                .with_system(button_connector::<prefabs::PrefabBtn>.chain(prefabs::prefab_btn_handler))
+                .with_system(prefabs::save_prefab_submit)
+                .with_system(prefabs::refresh_prefab_list)
+                .with_system(button_connector::<inspector::InspectorFieldBtn>.chain(inspector::inspector_btn_handler))
+                .with_system(inspector::inspector_submit)
+                .with_system(inspector::refresh_inspector_ui)
+                .with_system(button_connector::<layers::LayerBtn>.chain(layers::layer_btn_handler))
+                .with_system(layers::apply_layers)
+                .with_system(layers::refresh_layer_ui)
+                .with_system(button_connector::<outliner::OutlinerBtn>.chain(outliner::outliner_btn_handler))
+                .with_system(outliner::outliner_scroll)
+                .with_system(outliner::refresh_outliner_ui)
         );
         app.add_system_set(
-            SystemSet::on_enter(AppState::DevEditor).after("editorui")
-                // add spawn buttons for blueprints:
-                .with_system(ui::add_spawn_button::<gamecollider::Wall>)
-                .with_system(ui::add_spawn_button::<gamecollider::HurtZone>)
-                .with_system(ui::add_spawn_button::<gamecollider::SpawnZone>)
-                .with_system(ui::add_spawn_button::<gamecollider::WinZone>)
-                .with_system(ui::add_spawn_button::<Medkit>)
-                .with_system(ui::add_spawn_button::<AmmoBox>)
+            SystemSet::on_update(AppState::DevEditor)
+                .with_system(inspector::gather_inspector_fields.exclusive_system())
         );
     }
 }
@@ -143,6 +266,15 @@ enum ToolState {
     Inactive,
 }
 
+/// Like `SystemSet::on_update(AppState::DevEditor)`, for stages without the state driver
+fn in_editor(state: Res<State<AppState>>) -> ShouldRun {
+    if state.current() == &AppState::DevEditor {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
 fn enter_exit_editor(
     kbd: Res<Input<KeyCode>>,
     mut state: ResMut<State<AppState>>,
@@ -179,6 +311,9 @@ fn tool_hotkeys(
     if kbd.just_pressed(KeyCode::R) {
         newtool = Some(UsingTool::EditCollider);
     }
+    if kbd.just_pressed(KeyCode::K) {
+        newtool = Some(UsingTool::Link);
+    }
 
     if let Some(newtool) = newtool {
         *tool = newtool;
@@ -0,0 +1,266 @@
+//! The editor camera
+//!
+//! - Right-drag: pan
+//! - Mouse wheel: zoom around the cursor
+//! - F: frame the selection
+//! - Home: fit the whole level
+//! - Ctrl+1..9: save a bookmark, 1..9: go back to it
+//!
+//! Bookmarks are saved with the scene. The camera eases towards where
+//! it was sent, rather than jumping.
+
+use bevy::input::mouse::{MouseMotion, MouseWheel};
+use bevy::prelude::*;
+
+use crate::game::GameCleanup;
+use crate::scene_exporter::SaveSceneMarker;
+use crate::util::{MainCamera, WorldCursor};
+
+use super::Editable;
+use super::collider::{EditableCircle, EditableCollider, EditablePolygon};
+use super::outliner::{outliner_hovered, OutlinerPanel};
+use super::select::Selection;
+
+const ZOOM_STEP: f32 = 1.25;
+const MIN_SCALE: f32 = 0.1;
+const MAX_SCALE: f32 = 50.0;
+/// How quickly the camera catches up with its target (higher is faster)
+const SMOOTHING: f32 = 12.0;
+/// How much of the window framed things should fill
+const FRAME_FILL: f32 = 0.8;
+/// Don't zoom in further than this many world units across, when framing
+const FRAME_MIN_EXTENT: f32 = 128.0;
+
+const BOOKMARK_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1, KeyCode::Key2, KeyCode::Key3,
+    KeyCode::Key4, KeyCode::Key5, KeyCode::Key6,
+    KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+];
+
+/// Where the editor camera is heading
+///
+/// A scale of zero means not set; it is taken from the camera when entering the editor.
+#[derive(Default)]
+pub struct EditorCameraTarget {
+    pub translation: Vec2,
+    pub scale: f32,
+}
+
+/// A saved camera view, for the number keys
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct CameraBookmark {
+    /// 1 to 9
+    pub slot: u32,
+    pub translation: Vec2,
+    pub scale: f32,
+}
+
+/// Bookmarks go away with the level, like blueprints (whether saved now or loaded)
+pub(super) fn init_camera_bookmarks(
+    mut cmd: Commands,
+    q: Query<Entity, Added<CameraBookmark>>,
+) {
+    for e in q.iter() {
+        cmd.entity(e).insert(GameCleanup);
+    }
+}
+
+pub(super) fn init_camera_target(
+    mut target: ResMut<EditorCameraTarget>,
+    q_cam: Query<&Transform, With<MainCamera>>,
+) {
+    if let Ok(xf) = q_cam.get_single() {
+        target.translation = xf.translation.truncate();
+        target.scale = xf.scale.x;
+    }
+}
+
+pub(super) fn reset_camera_target(mut target: ResMut<EditorCameraTarget>) {
+    *target = EditorCameraTarget::default();
+}
+
+pub(super) fn editor_camera(
+    mut target: ResMut<EditorCameraTarget>,
+    mut q_cam: Query<&mut Transform, With<MainCamera>>,
+    mut evr_motion: EventReader<MouseMotion>,
+    btn: Res<Input<MouseButton>>,
+) {
+    if btn.pressed(MouseButton::Right) {
+        let mut xf = q_cam.single_mut();
+        let mut delta = Vec2::ZERO;
+        for ev in evr_motion.iter() {
+            delta += ev.delta;
+        }
+        delta *= xf.scale.truncate();
+        // panning follows the mouse directly
+        xf.translation.x -= delta.x;
+        xf.translation.y += delta.y;
+        target.translation.x -= delta.x;
+        target.translation.y += delta.y;
+    }
+}
+
+/// Zoom, keeping the point under the cursor in place
+pub(super) fn editor_camera_zoom(
+    mut target: ResMut<EditorCameraTarget>,
+    crs: Res<WorldCursor>,
+    q_cam: Query<&Transform, With<MainCamera>>,
+    mut evr_motion: EventReader<MouseWheel>,
+    q_panel: Query<&Interaction, With<OutlinerPanel>>,
+) {
+    // the wheel scrolls the outliner instead
+    if outliner_hovered(&q_panel) {
+        evr_motion.iter().for_each(drop);
+        return;
+    }
+    let xf = if let Ok(xf) = q_cam.get_single() {
+        xf
+    } else {
+        return;
+    };
+    let old_scale = target.scale;
+    let mut scale = old_scale;
+    for ev in evr_motion.iter() {
+        if ev.y > 0.0 {
+            scale *= ZOOM_STEP;
+        } else if ev.y < 0.0 {
+            scale /= ZOOM_STEP;
+        }
+    }
+    let scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    if scale == old_scale || old_scale <= 0.0 {
+        return;
+    }
+    // where the cursor is on the screen, from the centre, in pixels
+    let screen_offset = (crs.0 - xf.translation.truncate()) / xf.scale.x;
+    let pivot = target.translation + screen_offset * old_scale;
+    target.translation = pivot - screen_offset * scale;
+    target.scale = scale;
+}
+
+/// Ease the camera towards its target
+pub(super) fn editor_camera_smooth(
+    time: Res<Time>,
+    target: Res<EditorCameraTarget>,
+    mut q_cam: Query<&mut Transform, With<MainCamera>>,
+) {
+    if target.scale <= 0.0 {
+        return;
+    }
+    let mut xf = if let Ok(xf) = q_cam.get_single_mut() {
+        xf
+    } else {
+        return;
+    };
+    let pos = xf.translation.truncate();
+    if pos == target.translation && xf.scale.x == target.scale {
+        return;
+    }
+    let t = 1.0 - (-SMOOTHING * time.delta_seconds()).exp();
+    let mut new_pos = pos.lerp(target.translation, t);
+    let mut new_scale = xf.scale.x + (target.scale - xf.scale.x) * t;
+    // close enough; stop, so we don't touch the transform every frame
+    if new_pos.distance(target.translation) < 0.1 * target.scale
+        && (new_scale - target.scale).abs() < 0.001 * target.scale
+    {
+        new_pos = target.translation;
+        new_scale = target.scale;
+    }
+    xf.translation.x = new_pos.x;
+    xf.translation.y = new_pos.y;
+    xf.scale = Vec3::splat(new_scale);
+}
+
+/// Point the camera target at a rectangle of the world, so that it fills the window
+fn frame_bounds(target: &mut EditorCameraTarget, wnds: &Windows, min: Vec2, max: Vec2) {
+    let wnd = if let Some(wnd) = wnds.get_primary() {
+        wnd
+    } else {
+        return;
+    };
+    let window_size = Vec2::new(wnd.width(), wnd.height()) * FRAME_FILL;
+    let extent = (max - min).max(Vec2::splat(FRAME_MIN_EXTENT));
+    target.translation = (min + max) / 2.0;
+    target.scale = (extent / window_size).max_element().clamp(MIN_SCALE, MAX_SCALE);
+}
+
+/// Grow the bounds to include a circle
+fn add_bounds(bounds: &mut Option<(Vec2, Vec2)>, center: Vec2, radius: f32) {
+    let (min, max) = bounds.get_or_insert((center, center));
+    *min = min.min(center - Vec2::splat(radius));
+    *max = max.max(center + Vec2::splat(radius));
+}
+
+/// F: frame the selection
+/// Home: fit everything editable
+pub(super) fn keyboard_frame(
+    kbd: Res<Input<KeyCode>>,
+    wnds: Res<Windows>,
+    mut target: ResMut<EditorCameraTarget>,
+    q_sel: Query<(&GlobalTransform, &Sprite), With<Selection>>,
+    q_level: Query<(
+        &GlobalTransform,
+        Option<&EditableCollider>,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    ), With<Editable>>,
+) {
+    let mut bounds = None;
+    if kbd.just_pressed(KeyCode::F) {
+        // the selection sprites are already sized to what they select
+        for (gxf, spr) in q_sel.iter() {
+            let half = spr.custom_size.unwrap_or_default() * gxf.scale.truncate() / 2.0;
+            add_bounds(&mut bounds, gxf.translation.truncate(), half.length());
+        }
+    } else if kbd.just_pressed(KeyCode::Home) {
+        for (gxf, cuboid, circle, polygon) in q_level.iter() {
+            let radius = if let Some(circle) = circle {
+                circle.radius
+            } else if let Some(polygon) = polygon {
+                polygon.points.iter().fold(0.0, |acc: f32, p| acc.max(p.length()))
+            } else {
+                cuboid.map(|cuboid| cuboid.half_extends.length()).unwrap_or(0.0)
+            };
+            add_bounds(&mut bounds, gxf.translation.truncate(), radius * gxf.scale.x);
+        }
+    }
+    if let Some((min, max)) = bounds {
+        frame_bounds(&mut target, &wnds, min, max);
+    }
+}
+
+/// Ctrl+1..9: save the current view, 1..9: go back to it
+pub(super) fn keyboard_camera_bookmarks(
+    mut cmd: Commands,
+    kbd: Res<Input<KeyCode>>,
+    mut target: ResMut<EditorCameraTarget>,
+    mut q: Query<&mut CameraBookmark>,
+) {
+    let ctrl = kbd.pressed(KeyCode::LControl) || kbd.pressed(KeyCode::RControl);
+    for (i, key) in BOOKMARK_KEYS.iter().enumerate() {
+        if !kbd.just_pressed(*key) {
+            continue;
+        }
+        let slot = i as u32 + 1;
+        let existing = q.iter_mut().find(|bookmark| bookmark.slot == slot);
+        if ctrl {
+            let bookmark = CameraBookmark {
+                slot,
+                translation: target.translation,
+                scale: target.scale,
+            };
+            if let Some(mut existing) = existing {
+                *existing = bookmark;
+            } else {
+                cmd.spawn().insert(bookmark).insert(SaveSceneMarker);
+            }
+            info!("Saved camera bookmark {}", slot);
+        } else if let Some(bookmark) = existing {
+            target.translation = bookmark.translation;
+            target.scale = bookmark.scale;
+        } else {
+            info!("No camera bookmark {} (Ctrl+{} to save one)", slot, slot);
+        }
+    }
+}
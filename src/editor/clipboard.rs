@@ -0,0 +1,126 @@
+//! Copy, paste and duplicate for editor selections
+//!
+//! Works with anything that is saved with the scene: the selected entities
+//! (and their descendants) are exported like a prefab, keeping their offsets
+//! from each other. The clipboard survives loading another scene.
+//!
+//! Pasted entities follow the cursor until placed, like newly spawned ones.
+
+use bevy::ecs::entity::EntityMap;
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::scene::DynamicScene;
+
+use crate::scene_exporter::centered_scene;
+use crate::scene_exporter::stable_id::assign_copied_ids;
+use crate::util::WorldCursor;
+
+use super::select::{ActiveSelection, PendingSelect, Selections};
+use super::{NewlySpawned, ToolState};
+
+#[derive(Default)]
+pub struct EditorClipboard {
+    scene: Option<DynamicScene>,
+}
+
+pub enum ClipboardEvent {
+    Copy,
+    Paste,
+    Duplicate,
+}
+
+/// Ctrl+C: copy, Ctrl+V: paste, D: duplicate
+pub(super) fn clipboard_hotkeys(
+    kbd: Res<Input<KeyCode>>,
+    toolstate: Res<State<ToolState>>,
+    mut evw: EventWriter<ClipboardEvent>,
+) {
+    // not while something is still being placed
+    if !matches!(toolstate.current(), ToolState::Using(_)) {
+        return;
+    }
+    let ctrl = kbd.pressed(KeyCode::LControl) || kbd.pressed(KeyCode::RControl);
+    if ctrl && kbd.just_pressed(KeyCode::C) {
+        evw.send(ClipboardEvent::Copy);
+    }
+    if ctrl && kbd.just_pressed(KeyCode::V) {
+        evw.send(ClipboardEvent::Paste);
+    }
+    if !ctrl && kbd.just_pressed(KeyCode::D) {
+        evw.send(ClipboardEvent::Duplicate);
+    }
+}
+
+pub(super) fn apply_clipboard(world: &mut World) {
+    let events: Vec<ClipboardEvent> = world
+        .get_resource_mut::<Events<ClipboardEvent>>().unwrap()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let selected: Vec<Entity> = world.get_resource::<Selections>().unwrap()
+            .0.keys().copied().collect();
+        match ev {
+            ClipboardEvent::Copy => {
+                if let Some((scene, _)) = centered_scene(world, &selected) {
+                    info!("Copied {} entities", scene.entities.len());
+                    world.get_resource_mut::<EditorClipboard>().unwrap().scene = Some(scene);
+                }
+            }
+            ClipboardEvent::Paste => {
+                let pos = world.get_resource::<WorldCursor>().unwrap().0;
+                // take it out for a moment, so we can spawn into the world
+                let scene = world.get_resource_mut::<EditorClipboard>().unwrap().scene.take();
+                if let Some(scene) = scene {
+                    spawn_pasted(world, &scene, pos);
+                    world.get_resource_mut::<EditorClipboard>().unwrap().scene = Some(scene);
+                }
+            }
+            ClipboardEvent::Duplicate => {
+                // in place, without touching the clipboard
+                if let Some((scene, origin)) = centered_scene(world, &selected) {
+                    spawn_pasted(world, &scene, origin);
+                }
+            }
+        }
+    }
+}
+
+fn spawn_pasted(world: &mut World, scene: &DynamicScene, pos: Vec2) {
+    let mut entity_map = EntityMap::default();
+    if let Err(e) = scene.write_to_world(world, &mut entity_map) {
+        error!("Could not paste: {:?}", e);
+        return;
+    }
+    let pasted: Vec<Entity> = entity_map.values().collect();
+    assign_copied_ids(world, &pasted);
+
+    // the pasted entities replace the selection
+    let old_sels: Vec<Entity> = world.get_resource_mut::<Selections>().unwrap()
+        .0.drain().map(|(_, sel)| sel).collect();
+    for sel in old_sels {
+        if let Some(emut) = world.get_entity_mut(sel) {
+            emut.despawn_recursive();
+        }
+    }
+
+    let mut roots = Vec::new();
+    for e in entity_map.values() {
+        let mut emut = world.entity_mut(e);
+        if emut.contains::<Parent>() {
+            continue;
+        }
+        if let Some(mut xf) = emut.get_mut::<Transform>() {
+            xf.translation.x += pos.x;
+            xf.translation.y += pos.y;
+        }
+        // selected once placed
+        emut.insert(NewlySpawned).insert(PendingSelect);
+        roots.push(e);
+    }
+    roots.sort_by_key(|e| e.id());
+    world.get_resource_mut::<ActiveSelection>().unwrap().0 = roots.first().copied();
+
+    world.get_resource_mut::<State<ToolState>>().unwrap()
+        .set(ToolState::Spawning).ok();
+}
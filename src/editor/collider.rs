@@ -1,9 +1,12 @@
-use bevy::{prelude::*, math::{const_vec2, Mat2}, input::mouse::MouseMotion};
+use bevy::{prelude::*, math::const_vec2};
 use heron::CollisionShape;
+use bevy_prototype_debug_lines::DebugLines;
 
-use crate::{util::{WorldCursor, WorldCursorPrev}};
+use crate::{util::WorldCursor, game::collider::{ColliderGeometry, ColliderKind}};
 
-use super::select::Selection;
+use super::layers::LayerHidden;
+use super::select::{Selection, Selections};
+use super::snap::SnapSettings;
 
 #[derive(Component, Clone, Reflect)]
 #[reflect(Component)]
@@ -19,12 +22,44 @@ impl Default for EditableCollider {
     }
 }
 
+/// Circular collider, instead of `EditableCollider`
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct EditableCircle {
+    pub radius: f32,
+}
+
+impl Default for EditableCircle {
+    fn default() -> Self {
+        EditableCircle {
+            radius: 30.0,
+        }
+    }
+}
+
+/// Convex polygon collider, instead of `EditableCollider`
+///
+/// Points are in the local space of the entity.
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct EditablePolygon {
+    pub points: Vec<Vec2>,
+}
+
 #[derive(Component)]
 pub struct ColliderEditorVisColor(pub Color);
 
 #[derive(Component)]
 pub struct ColliderVisualized;
 
+/// For entities with no visuals of their own, to have something
+/// to see and click on in the editor
+#[derive(Component, Clone, Copy)]
+pub struct EditorIcon {
+    pub color: Color,
+    pub size: Vec2,
+}
+
 pub fn visualize_spriteless_colliders(
     mut cmd: Commands,
     q: Query<(
@@ -33,7 +68,8 @@ pub fn visualize_spriteless_colliders(
         &ColliderEditorVisColor
     ), (
         Without<Sprite>,
-        Without<ColliderVisualized>
+        Without<ColliderVisualized>,
+        Without<LayerHidden>,
     )>
 ) {
     for (e, edit, viscolor) in q.iter() {
@@ -53,6 +89,27 @@ pub fn visualize_spriteless_colliders(
     }
 }
 
+pub fn visualize_editor_icons(
+    mut cmd: Commands,
+    q: Query<(Entity, &EditorIcon), (Without<Sprite>, Without<ColliderVisualized>, Without<LayerHidden>)>,
+) {
+    for (e, icon) in q.iter() {
+        let bundle = SpriteBundle {
+            sprite: Sprite {
+                color: icon.color,
+                custom_size: Some(icon.size),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cmd.entity(e)
+            .insert(ColliderVisualized)
+            .insert(bundle.sprite)
+            .insert(bundle.texture)
+            .insert(bundle.visibility);
+    }
+}
+
 pub fn update_collider_visualization(
     mut q: Query<(&mut Sprite, &EditableCollider), With<ColliderVisualized>>
 ) {
@@ -73,22 +130,154 @@ pub fn cleanup_collider_visualizations(
     }
 }
 
+/// Circles and polygons can't be shown with a sprite, so draw their outline
+pub fn visualize_shaped_colliders(
+    mut lines: ResMut<DebugLines>,
+    q: Query<(
+        &GlobalTransform,
+        &ColliderEditorVisColor,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    ), (Without<LayerHidden>, Or<(With<EditableCircle>, With<EditablePolygon>)>)>,
+) {
+    for (gxf, viscolor, circle, polygon) in q.iter() {
+        let points = if let Some(circle) = circle {
+            ColliderGeometry::circle_points(circle.radius)
+        } else if let Some(polygon) = polygon {
+            polygon.points.clone()
+        } else {
+            continue;
+        };
+        let mut color = viscolor.0;
+        color.set_a(1.0);
+        let world: Vec<Vec3> = points.iter()
+            .map(|p| gxf.mul_vec3(p.extend(0.0)))
+            .collect();
+        for i in 0..world.len() {
+            let next = (i + 1) % world.len();
+            lines.line_colored(world[i], world[next], 0.0, color);
+        }
+    }
+}
+
+/// Tab: switch the selected colliders between box, circle and polygon
+pub(super) fn keyboard_cycle_collider_shape(
+    mut cmd: Commands,
+    kbd: Res<Input<KeyCode>>,
+    sels: Res<Selections>,
+    q: Query<(
+        &ColliderKind,
+        Option<&EditableCollider>,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    )>,
+) {
+    if !kbd.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    for e in sels.0.keys() {
+        let (kind, edit, circle, polygon) = if let Ok(x) = q.get(*e) {
+            x
+        } else {
+            continue;
+        };
+        if !kind.allows_shapes() {
+            continue;
+        }
+        if let Some(circle) = circle {
+            // a hexagon is a good start for shaping a polygon
+            let points = (0..6)
+                .map(|i| {
+                    let angle = i as f32 / 6.0 * std::f32::consts::TAU;
+                    Vec2::new(angle.cos(), angle.sin()) * circle.radius
+                })
+                .collect();
+            cmd.entity(*e)
+                .remove::<EditableCircle>()
+                .insert(EditablePolygon { points });
+        } else if let Some(polygon) = polygon {
+            let half_extends = polygon.points.iter()
+                .fold(Vec2::ZERO, |acc, p| acc.max(p.abs()));
+            cmd.entity(*e)
+                .remove::<EditablePolygon>()
+                .insert(EditableCollider { half_extends });
+        } else if let Some(edit) = edit {
+            cmd.entity(*e)
+                .remove::<EditableCollider>()
+                // the box sprite
+                .remove::<ColliderVisualized>()
+                .remove::<Sprite>()
+                .remove::<Handle<Image>>()
+                .insert(EditableCircle {
+                    radius: edit.half_extends.max_element(),
+                });
+        }
+    }
+}
+
 const DRAGHANDLE_RADIUS: f32 = 8.0;
+const DRAGHANDLE_EDGE_COLOR: Color = Color::rgb(0.75, 0.75, 1.0);
+const DRAGHANDLE_VERTEX_COLOR: Color = Color::rgb(1.0, 1.0, 0.5);
+
+/// What dragging a handle changes
+#[derive(Debug, Clone, Copy)]
+enum HandleKind {
+    /// Corner or edge of a box; which sides move (0 for neither)
+    Extents(Vec2),
+    Radius,
+    Vertex(usize),
+}
 
 #[derive(Debug, Component, Clone, Copy)]
 pub struct DragHandle {
-    drag: Vec2,
+    kind: HandleKind,
     target: Entity,
 }
 
+impl DragHandle {
+    /// Where the handle is, in the space of its target
+    fn position(
+        &self,
+        edit: Option<&EditableCollider>,
+        circle: Option<&EditableCircle>,
+        polygon: Option<&EditablePolygon>,
+    ) -> Option<Vec2> {
+        match self.kind {
+            HandleKind::Extents(drag) => edit.map(|edit| edit.half_extends * drag),
+            HandleKind::Radius => circle.map(|circle| Vec2::new(circle.radius, 0.0)),
+            HandleKind::Vertex(i) => polygon.and_then(|polygon| polygon.points.get(i).copied()),
+        }
+    }
+}
+
+/// The handle being dragged, and the state of its target when the drag started
+#[derive(Debug, Clone, Copy)]
+pub struct HandleDrag {
+    handle: DragHandle,
+    crs: Vec2,
+    transform: Transform,
+    global: GlobalTransform,
+    half_extends: Vec2,
+    /// Position of the handle, in the space of the target
+    handle_pos: Vec2,
+}
+
 #[derive(Default)]
-pub struct ActiveDraghandle(Option<(DragHandle, f32)>);
+pub struct ActiveDraghandle(Option<HandleDrag>);
+
+type ShapeQuery<'w, 's> = Query<'w, 's, (
+    Option<&'static EditableCollider>,
+    Option<&'static EditableCircle>,
+    Option<&'static EditablePolygon>,
+)>;
 
 pub fn mouse_select_draghandle(
     crs: Res<WorldCursor>,
     mut dh_active: ResMut<ActiveDraghandle>,
     btn: Res<Input<MouseButton>>,
     q_draghandle: Query<(&GlobalTransform, &DragHandle)>,
+    q_tgt: Query<(&Transform, &GlobalTransform)>,
+    q_shape: ShapeQuery,
 ) {
     if btn.just_pressed(MouseButton::Left) {
         let mut best = None;
@@ -99,51 +288,116 @@ pub fn mouse_select_draghandle(
             if distance > DRAGHANDLE_RADIUS {
                 continue;
             }
-            let rot = xf.rotation.to_axis_angle().1;
             if let Some((d, _)) = best {
                 if distance < d {
-                    best = Some((distance, (*dh, rot)));
+                    best = Some((distance, *dh));
                 }
             } else {
-                best = Some((distance, (*dh, rot)));
+                best = Some((distance, *dh));
             }
         }
-        dh_active.0 = best.map(|(_, dh)| dh);
+        dh_active.0 = best.and_then(|(_, handle)| {
+            let (xf, gxf) = q_tgt.get(handle.target).ok()?;
+            let (edit, circle, polygon) = q_shape.get(handle.target).ok()?;
+            Some(HandleDrag {
+                handle,
+                crs: crs.0,
+                transform: *xf,
+                global: *gxf,
+                half_extends: edit.map(|edit| edit.half_extends).unwrap_or_default(),
+                handle_pos: handle.position(edit, circle, polygon)?,
+            })
+        });
     }
     if btn.just_released(MouseButton::Left) {
         dh_active.0 = None;
     }
 }
 
+/// Reshape the collider in its own local frame
+///
+/// Boxes keep the opposite side in place; circles keep their center.
 pub fn mouse_drag_handle(
-    mut motion: EventReader<MouseMotion>,
+    crs: Res<WorldCursor>,
+    kbd: Res<Input<KeyCode>>,
+    snap: Res<SnapSettings>,
     dh: Res<ActiveDraghandle>,
-    mut q_tgt: Query<(&mut Transform, &mut EditableCollider)>,
+    mut q_tgt: Query<(
+        &mut Transform,
+        Option<&mut EditableCollider>,
+        Option<&mut EditableCircle>,
+        Option<&mut EditablePolygon>,
+    )>,
 ) {
     const COLLIDER_MINSIZE: f32 = 4.0;
-    if let Some((dh, rot)) = dh.0 {
-        if let Ok((mut xf, mut edit)) = q_tgt.get_mut(dh.target) {
-            let mut delta = Vec2::ZERO;
-            for ev in motion.iter() {
-                delta += ev.delta;
+    let drag = if let Some(drag) = dh.0 {
+        drag
+    } else {
+        return;
+    };
+    let (mut xf, edit, circle, polygon) = if let Ok(x) = q_tgt.get_mut(drag.handle.target) {
+        x
+    } else {
+        return;
+    };
+    let snapping = snap.active(&kbd);
+
+    // the cursor movement, in the space of the collider
+    let delta = (crs.0 - drag.crs).extend(0.0);
+    let delta = (drag.global.rotation.inverse() * delta).truncate()
+        / drag.global.scale.truncate();
+
+    match drag.handle.kind {
+        HandleKind::Extents(dir) => {
+            let mut edit = if let Some(edit) = edit {
+                edit
+            } else {
+                return;
+            };
+            // the opposite side stays in place, so the extents change by half the drag;
+            // edge handles have 0 in the axis they don't change
+            let mut half_extends = drag.half_extends + delta / 2.0 * dir;
+            for axis in 0..2 {
+                if dir[axis] == 0.0 {
+                    continue;
+                }
+                let mut ext = half_extends[axis].max(COLLIDER_MINSIZE);
+                if snapping {
+                    ext = snap.snap_extents(Vec2::splat(ext)).x;
+                }
+                half_extends[axis] = ext;
             }
-            delta.y = -delta.y;
-            delta /= 2.0;
-            delta *= dh.drag;
+            let change = half_extends - drag.half_extends;
 
-            if edit.half_extends.x + delta.x < COLLIDER_MINSIZE {
-                delta.x = 0.0;
+            // move the center by the same amount, back in the space of the parent
+            let offset = (change * dir).extend(0.0) * drag.transform.scale;
+            xf.translation = drag.transform.translation + drag.transform.rotation * offset;
+            edit.half_extends = half_extends;
+        }
+        HandleKind::Radius => {
+            if let Some(mut circle) = circle {
+                let mut radius = (drag.handle_pos.x + delta.x).max(COLLIDER_MINSIZE);
+                if snapping {
+                    radius = snap.snap_extents(Vec2::splat(radius)).x;
+                }
+                circle.radius = radius;
             }
-            if edit.half_extends.y + delta.y < COLLIDER_MINSIZE {
-                delta.y = 0.0;
+        }
+        HandleKind::Vertex(i) => {
+            if let Some(mut polygon) = polygon {
+                let mut pos = drag.handle_pos + delta;
+                if snapping {
+                    pos = snap.snap_pos(pos);
+                }
+                let mut points = polygon.points.clone();
+                if let Some(point) = points.get_mut(i) {
+                    *point = pos;
+                }
+                // the vertex stays where it was until the shape is convex again
+                if ColliderGeometry::is_convex(&points) {
+                    polygon.points = points;
+                }
             }
-
-            // let mrot = Mat2::from_angle(rot);
-
-            // xf.translation += (mrot * (delta * dh.drag)).extend(0.0);
-            // edit.half_extends += mrot * delta;
-            xf.translation += (delta * dh.drag).extend(0.0);
-            edit.half_extends += delta;
         }
     }
 }
@@ -151,42 +405,69 @@ pub fn mouse_drag_handle(
 pub fn spawn_draghandles(
     mut cmd: Commands,
     q_sel: Query<(Entity, &Selection)>,
-    q_tgt: Query<&EditableCollider>,
+    q_shape: ShapeQuery,
 ) {
-    const DRAGS: [Vec2; 4] = [
+    const DRAGS: [Vec2; 8] = [
+        // corners
         const_vec2!([1.0, 1.0]),
         const_vec2!([-1.0, 1.0]),
         const_vec2!([1.0, -1.0]),
         const_vec2!([-1.0, -1.0]),
+        // edges
+        const_vec2!([1.0, 0.0]),
+        const_vec2!([-1.0, 0.0]),
+        const_vec2!([0.0, 1.0]),
+        const_vec2!([0.0, -1.0]),
     ];
     for (e, sel) in q_sel.iter() {
-        if let Ok(edit) = q_tgt.get(sel.0) {
-            for drag in DRAGS {
-                let h = cmd.spawn_bundle(SpriteBundle {
-                    sprite: Sprite {
-                        color: Color::WHITE,
-                        custom_size: Some(Vec2::splat(DRAGHANDLE_RADIUS)),
-                        ..Default::default()
-                    },
-                    transform: Transform::from_translation((edit.half_extends * drag).extend(99.0)),
+        let (edit, circle, polygon) = if let Ok(x) = q_shape.get(sel.0) {
+            x
+        } else {
+            continue;
+        };
+        let kinds: Vec<HandleKind> = if circle.is_some() {
+            vec![HandleKind::Radius]
+        } else if let Some(polygon) = polygon {
+            (0..polygon.points.len()).map(HandleKind::Vertex).collect()
+        } else if edit.is_some() {
+            DRAGS.iter().map(|drag| HandleKind::Extents(*drag)).collect()
+        } else {
+            continue;
+        };
+        for kind in kinds {
+            let dh = DragHandle {
+                kind,
+                target: sel.0,
+            };
+            let color = match kind {
+                HandleKind::Extents(drag) if drag.x == 0.0 || drag.y == 0.0 => DRAGHANDLE_EDGE_COLOR,
+                HandleKind::Extents(_) => Color::WHITE,
+                HandleKind::Radius | HandleKind::Vertex(_) => DRAGHANDLE_VERTEX_COLOR,
+            };
+            let pos = dh.position(edit, circle, polygon).unwrap_or_default();
+            let h = cmd.spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(DRAGHANDLE_RADIUS)),
                     ..Default::default()
-                }).insert(DragHandle {
-                    drag,
-                    target: sel.0,
-                }).id();
-                cmd.entity(e).push_children(&[h]);
-            }
+                },
+                transform: Transform::from_translation(pos.extend(99.0)),
+                ..Default::default()
+            }).insert(dh).id();
+            cmd.entity(e).push_children(&[h]);
         }
     }
 }
 
 pub fn draghandles_track_collider(
     mut q_dh: Query<(&mut Transform, &DragHandle)>,
-    q_tgt: Query<&EditableCollider>,
+    q_shape: ShapeQuery,
 ) {
     for (mut xf, dh) in q_dh.iter_mut() {
-        if let Ok(edit) = q_tgt.get(dh.target) {
-            xf.translation = (edit.half_extends * dh.drag).extend(99.0);
+        if let Ok((edit, circle, polygon)) = q_shape.get(dh.target) {
+            if let Some(pos) = dh.position(edit, circle, polygon) {
+                xf.translation = pos.extend(99.0);
+            }
         }
     }
 }
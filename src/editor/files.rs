@@ -0,0 +1,257 @@
+//! Editor panel for opening and saving scene files
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::scene_exporter::{self, SaveScenePath, SaveSceneEvent, LoadSceneEvent, ClearSceneEvent};
+use crate::ui::{UiAssets, UiConfig};
+
+use super::EditorHideCleanup;
+use super::textinput::{TextInput, TextInputSubmit, TextInputTarget};
+
+#[derive(Default)]
+pub struct FileListParent(Option<Entity>);
+
+#[derive(Component)]
+pub(super) struct CurrentFileText;
+
+#[derive(Component, Clone)]
+pub(super) enum FileBtn {
+    Open(PathBuf),
+    Save,
+    SaveAs,
+    New,
+}
+
+pub(super) fn file_btn_handler(
+    In(clicked): In<Option<FileBtn>>,
+    mut btn: ResMut<Input<MouseButton>>,
+    mut path: ResMut<SaveScenePath>,
+    mut input: ResMut<TextInput>,
+    mut evw_save: EventWriter<SaveSceneEvent>,
+    mut evw_load: EventWriter<LoadSceneEvent>,
+    mut evw_clear: EventWriter<ClearSceneEvent>,
+) {
+    let clicked = if let Some(clicked) = clicked {
+        clicked
+    } else {
+        return;
+    };
+    btn.clear_just_pressed(MouseButton::Left);
+    match clicked {
+        FileBtn::Open(file) => {
+            evw_load.send(LoadSceneEvent(file.clone()));
+            path.0 = file;
+        }
+        FileBtn::Save => {
+            evw_save.send(SaveSceneEvent);
+        }
+        FileBtn::SaveAs => {
+            let name = path.0.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            input.begin(TextInputTarget::SaveSceneAs, name);
+        }
+        FileBtn::New => {
+            evw_clear.send(ClearSceneEvent);
+            path.0 = path.0.with_file_name("untitled.scn.ron");
+        }
+    }
+}
+
+pub(super) fn save_as_submit(
+    mut evr: EventReader<TextInputSubmit>,
+    mut path: ResMut<SaveScenePath>,
+    mut evw_save: EventWriter<SaveSceneEvent>,
+) {
+    for ev in evr.iter() {
+        if ev.target != TextInputTarget::SaveSceneAs {
+            continue;
+        }
+        let mut name = ev.text.trim().to_owned();
+        if name.is_empty() {
+            continue;
+        }
+        if !name.ends_with(".scn.ron") {
+            name.push_str(".scn.ron");
+        }
+        // save next to the current file
+        path.0 = path.0.with_file_name(name);
+        evw_save.send(SaveSceneEvent);
+    }
+}
+
+pub(super) fn update_current_file_text(
+    path: Res<SaveScenePath>,
+    input: Res<TextInput>,
+    mut q: Query<&mut Text, With<CurrentFileText>>,
+) {
+    let value = match input.active() {
+        Some(TextInputTarget::SaveSceneAs) => format!("Save as: {}_", input.buffer),
+        Some(TextInputTarget::SavePrefab) => format!("Prefab name: {}_", input.buffer),
+        Some(TextInputTarget::InspectorField) | None => format!("Current: {}", path.0.display()),
+    };
+    for mut text in q.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+/// Rebuild the list of scene files whenever we might have created a new one
+///
+/// Happens one frame late, to give the scene exporter time to write the file.
+pub(super) fn refresh_file_list(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    uiassets: Res<UiAssets>,
+    parent: Res<FileListParent>,
+    path: Res<SaveScenePath>,
+    mut evr_save: EventReader<SaveSceneEvent>,
+    mut rescan: Local<bool>,
+) {
+    let trigger = parent.is_changed() || path.is_changed() || evr_save.iter().next().is_some();
+    if !std::mem::replace(&mut *rescan, trigger) {
+        return;
+    }
+    let list = if let Some(list) = parent.0 {
+        list
+    } else {
+        return;
+    };
+
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    cmd.entity(list).despawn_descendants();
+    for file in scene_exporter::find_scene_files() {
+        let label = file.display().to_string();
+        let btn = file_button(&mut cmd, &uicfg, &textstyle_btn, &label, FileBtn::Open(file));
+        cmd.entity(list).push_children(&[btn]);
+    }
+}
+
+pub(super) fn file_button<B: Component>(
+    cmd: &mut Commands,
+    uicfg: &UiConfig,
+    textstyle: &TextStyle,
+    label: &str,
+    btn: B,
+) -> Entity {
+    let btntext = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            label,
+            textstyle.clone(),
+            Default::default()
+        ),
+        ..Default::default()
+    }).id();
+
+    let btn = cmd.spawn_bundle(ButtonBundle {
+        color: UiColor(Color::rgb(0.75, 0.75, 0.75)),
+        style: uicfg.btn_style.clone(),
+        ..Default::default()
+    }).insert(btn).id();
+    cmd.entity(btn).push_children(&[btntext]);
+    btn
+}
+
+pub(super) fn spawn_files_ui(
+    mut cmd: Commands,
+    uiassets: Res<UiAssets>,
+    uicfg: Res<UiConfig>,
+    mut r_list: ResMut<FileListParent>,
+) {
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    let top = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::rgb(1.0, 1.0, 1.0)),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(20.0),
+                bottom: Val::Auto,
+                right: Val::Px(20.0),
+                left: Val::Auto,
+            },
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(EditorHideCleanup).id();
+
+    let heading_text = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "SCENES:",
+            uicfg.heading_style_text.clone(),
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let current = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "",
+            textstyle_btn.clone(),
+            Default::default()
+        ),
+        style: Style {
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(CurrentFileText).id();
+
+    let btnrow = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let btns = [
+        file_button(&mut cmd, &uicfg, &textstyle_btn, "Save", FileBtn::Save),
+        file_button(&mut cmd, &uicfg, &textstyle_btn, "Save As", FileBtn::SaveAs),
+        file_button(&mut cmd, &uicfg, &textstyle_btn, "New", FileBtn::New),
+    ];
+    cmd.entity(btnrow).push_children(&btns);
+
+    let list = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Stretch,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    cmd.entity(top).push_children(&[heading_text, current, btnrow, list]);
+
+    r_list.0 = Some(list);
+}
+
+pub(super) fn cleanup_files_ui(mut r_list: ResMut<FileListParent>) {
+    r_list.0 = None;
+}
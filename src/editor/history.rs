@@ -0,0 +1,425 @@
+//! Undo/redo for editor operations
+//!
+//! Every edit is recorded as a group of reversible `EditOp`s. Tools don't
+//! need to know about this:
+//! - transform and collider changes are picked up by comparing the selected
+//!   entities from when the mouse button is pressed to when it is released
+//! - spawns are recorded when a newly spawned entity gets placed
+//! - other changes to components (like from the inspector) are recorded as
+//!   `EditOp::Component`, with the reflected value from before and after
+//! - despawns must go through `DespawnEntitiesEvent`, so that a snapshot can
+//!   be taken before the entity is gone; entities that are not saved with the
+//!   scene can't be snapshot, so they are not despawned
+//!
+//! Entities that are despawned and respawned by undo/redo get new ids; all
+//! recorded operations are updated to refer to the new entity.
+
+use std::collections::VecDeque;
+
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
+use bevy::utils::HashMap;
+
+use crate::scene_exporter::{ClearSceneEvent, EntitySnapshot, LoadSceneEvent};
+
+use super::collider::{EditableCircle, EditableCollider, EditablePolygon};
+use super::select::Selections;
+use super::NewlySpawned;
+
+/// How many edits can be undone
+const MAX_HISTORY: usize = 100;
+
+pub enum EditOp {
+    Transform {
+        entity: Entity,
+        before: Transform,
+        after: Transform,
+    },
+    ColliderExtent {
+        entity: Entity,
+        before: Vec2,
+        after: Vec2,
+    },
+    CircleRadius {
+        entity: Entity,
+        before: f32,
+        after: f32,
+    },
+    PolygonPoints {
+        entity: Entity,
+        before: Vec<Vec2>,
+        after: Vec<Vec2>,
+    },
+    /// Any reflected component; `None` if the entity did not have it
+    Component {
+        entity: Entity,
+        /// Full type name, as registered
+        type_name: String,
+        before: Option<Box<dyn Reflect>>,
+        after: Option<Box<dyn Reflect>>,
+    },
+    /// The snapshot is taken when the spawn is undone
+    Spawn {
+        entity: Entity,
+        snapshot: Option<EntitySnapshot>,
+    },
+    /// The snapshot is taken when the entity is despawned
+    Despawn {
+        entity: Entity,
+        snapshot: Option<EntitySnapshot>,
+    },
+}
+
+impl EditOp {
+    /// A change of a component, if there was one
+    pub fn component(
+        entity: Entity,
+        type_name: &str,
+        before: Option<Box<dyn Reflect>>,
+        after: Option<Box<dyn Reflect>>,
+    ) -> Option<EditOp> {
+        let unchanged = match (&before, &after) {
+            (Some(before), Some(after)) => before.reflect_partial_eq(&**after).unwrap_or(false),
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            return None;
+        }
+        Some(EditOp::Component {
+            entity,
+            type_name: type_name.to_owned(),
+            before,
+            after,
+        })
+    }
+
+    fn remap(&mut self, old: Entity, new: Entity) {
+        let entity = match self {
+            EditOp::Transform { entity, .. } => entity,
+            EditOp::ColliderExtent { entity, .. } => entity,
+            EditOp::CircleRadius { entity, .. } => entity,
+            EditOp::PolygonPoints { entity, .. } => entity,
+            EditOp::Component { entity, .. } => entity,
+            EditOp::Spawn { entity, .. } => entity,
+            EditOp::Despawn { entity, .. } => entity,
+        };
+        if *entity == old {
+            *entity = new;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EditorHistory {
+    undo: VecDeque<Vec<EditOp>>,
+    redo: Vec<Vec<EditOp>>,
+}
+
+impl EditorHistory {
+    /// Record an edit that has already been done
+    pub fn push(&mut self, ops: Vec<EditOp>) {
+        if ops.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push_back(ops);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    fn remap(&mut self, mapping: &[(Entity, Entity)]) {
+        let ops = self.undo.iter_mut()
+            .chain(self.redo.iter_mut())
+            .flat_map(|group| group.iter_mut());
+        for op in ops {
+            for (old, new) in mapping {
+                op.remap(*old, *new);
+            }
+        }
+    }
+}
+
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+}
+
+/// Despawn scene entities from the editor, in a way that can be undone
+pub struct DespawnEntitiesEvent(pub Vec<Entity>);
+
+/// State of the selected entities when the mouse button was pressed
+#[derive(Default)]
+pub struct DragStart {
+    transforms: HashMap<Entity, Transform>,
+    extents: HashMap<Entity, Vec2>,
+    radii: HashMap<Entity, f32>,
+    polygons: HashMap<Entity, Vec<Vec2>>,
+}
+
+pub(super) fn history_hotkeys(
+    kbd: Res<Input<KeyCode>>,
+    mut evw: EventWriter<HistoryEvent>,
+) {
+    let ctrl = kbd.pressed(KeyCode::LControl) || kbd.pressed(KeyCode::RControl);
+    let shift = kbd.pressed(KeyCode::LShift) || kbd.pressed(KeyCode::RShift);
+    if !ctrl {
+        return;
+    }
+    if kbd.just_pressed(KeyCode::Z) {
+        evw.send(if shift { HistoryEvent::Redo } else { HistoryEvent::Undo });
+    }
+    if kbd.just_pressed(KeyCode::Y) {
+        evw.send(HistoryEvent::Redo);
+    }
+}
+
+/// Record transform/collider changes made by dragging with the mouse
+pub(super) fn record_drags(
+    btn: Res<Input<MouseButton>>,
+    sels: Res<Selections>,
+    mut start: ResMut<DragStart>,
+    mut history: ResMut<EditorHistory>,
+    q: Query<(
+        &Transform,
+        Option<&EditableCollider>,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    ), Without<NewlySpawned>>,
+) {
+    if btn.just_pressed(MouseButton::Left) {
+        start.transforms.clear();
+        start.extents.clear();
+        start.radii.clear();
+        start.polygons.clear();
+        for e in sels.0.keys() {
+            if let Ok((xf, edit, circle, polygon)) = q.get(*e) {
+                start.transforms.insert(*e, *xf);
+                if let Some(edit) = edit {
+                    start.extents.insert(*e, edit.half_extends);
+                }
+                if let Some(circle) = circle {
+                    start.radii.insert(*e, circle.radius);
+                }
+                if let Some(polygon) = polygon {
+                    start.polygons.insert(*e, polygon.points.clone());
+                }
+            }
+        }
+    }
+    if btn.just_released(MouseButton::Left) {
+        let mut ops = Vec::new();
+        for (e, before) in start.transforms.drain() {
+            if let Ok((xf, _, _, _)) = q.get(e) {
+                if *xf != before {
+                    ops.push(EditOp::Transform { entity: e, before, after: *xf });
+                }
+            }
+        }
+        for (e, before) in start.extents.drain() {
+            if let Ok((_, Some(edit), _, _)) = q.get(e) {
+                if edit.half_extends != before {
+                    ops.push(EditOp::ColliderExtent { entity: e, before, after: edit.half_extends });
+                }
+            }
+        }
+        for (e, before) in start.radii.drain() {
+            if let Ok((_, _, Some(circle), _)) = q.get(e) {
+                if circle.radius != before {
+                    ops.push(EditOp::CircleRadius { entity: e, before, after: circle.radius });
+                }
+            }
+        }
+        for (e, before) in start.polygons.drain() {
+            if let Ok((_, _, _, Some(polygon))) = q.get(e) {
+                if polygon.points != before {
+                    ops.push(EditOp::PolygonPoints { entity: e, before, after: polygon.points.clone() });
+                }
+            }
+        }
+        history.push(ops);
+    }
+}
+
+/// Newly spawned entities are recorded once they are placed
+pub(super) fn record_spawns(
+    removed: RemovedComponents<NewlySpawned>,
+    q: Query<(), With<Transform>>,
+    mut history: ResMut<EditorHistory>,
+) {
+    let ops: Vec<EditOp> = removed.iter()
+        .filter(|e| q.get(*e).is_ok())
+        .map(|entity| EditOp::Spawn { entity, snapshot: None })
+        .collect();
+    history.push(ops);
+}
+
+/// The old entities are gone when a scene is loaded
+pub(super) fn clear_history_on_scene_change(
+    evs_load: Res<Events<LoadSceneEvent>>,
+    evs_clear: Res<Events<ClearSceneEvent>>,
+    mut history: ResMut<EditorHistory>,
+) {
+    if !evs_load.is_empty() || !evs_clear.is_empty() {
+        history.clear();
+    }
+}
+
+pub(super) fn apply_despawns(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .get_resource_mut::<Events<DespawnEntitiesEvent>>().unwrap()
+        .drain()
+        .flat_map(|ev| ev.0)
+        .collect();
+
+    let mut ops = Vec::new();
+    for e in entities.iter() {
+        // will be despawned along with its ancestor
+        if has_ancestor_in(world, *e, &entities) {
+            continue;
+        }
+        if let Some(snapshot) = despawn_with_snapshot(world, *e) {
+            ops.push(EditOp::Despawn { entity: *e, snapshot: Some(snapshot) });
+        } else if world.get_entity(*e).is_some() {
+            warn!("Not deleting {:?}: it is not saved with the scene, so it could not be undone", e);
+        }
+    }
+    world.get_resource_mut::<EditorHistory>().unwrap().push(ops);
+}
+
+fn has_ancestor_in(world: &World, e: Entity, entities: &[Entity]) -> bool {
+    let mut cur = e;
+    while let Some(parent) = world.get::<Parent>(cur) {
+        if entities.contains(&parent.0) {
+            return true;
+        }
+        cur = parent.0;
+    }
+    false
+}
+
+/// Entities that can't be snapshot (like ones excluded from the scene) are left alone
+fn despawn_with_snapshot(world: &mut World, e: Entity) -> Option<EntitySnapshot> {
+    let snapshot = EntitySnapshot::take(world, e)?;
+    world.entity_mut(e).despawn_recursive();
+    Some(snapshot)
+}
+
+/// Point all recorded operations at respawned entities
+fn remap_all(world: &mut World, group: &mut [EditOp], mapping: &[(Entity, Entity)]) {
+    for op in group.iter_mut() {
+        for (old, new) in mapping {
+            op.remap(*old, *new);
+        }
+    }
+    world.get_resource_mut::<EditorHistory>().unwrap().remap(mapping);
+}
+
+pub(super) fn apply_history(world: &mut World) {
+    let events: Vec<HistoryEvent> = world
+        .get_resource_mut::<Events<HistoryEvent>>().unwrap()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let mut history = world.get_resource_mut::<EditorHistory>().unwrap();
+        let (mut group, undo) = match ev {
+            HistoryEvent::Undo => match history.undo.pop_back() {
+                Some(group) => (group, true),
+                None => continue,
+            },
+            HistoryEvent::Redo => match history.redo.pop() {
+                Some(group) => (group, false),
+                None => continue,
+            },
+        };
+
+        // undo in reverse order
+        let n = group.len();
+        for i in 0..n {
+            let i = if undo { n - 1 - i } else { i };
+            let mapping = apply_op(world, &mut group[i], undo);
+            if !mapping.is_empty() {
+                remap_all(world, &mut group, &mapping);
+            }
+        }
+
+        let mut history = world.get_resource_mut::<EditorHistory>().unwrap();
+        if undo {
+            history.redo.push(group);
+        } else {
+            history.undo.push_back(group);
+        }
+    }
+}
+
+/// Returns the (old, new) ids of any entities that were respawned
+fn apply_op(world: &mut World, op: &mut EditOp, undo: bool) -> Vec<(Entity, Entity)> {
+    match op {
+        EditOp::Transform { entity, before, after } => {
+            if let Some(mut xf) = world.get_mut::<Transform>(*entity) {
+                *xf = if undo { *before } else { *after };
+            }
+        }
+        EditOp::ColliderExtent { entity, before, after } => {
+            if let Some(mut edit) = world.get_mut::<EditableCollider>(*entity) {
+                edit.half_extends = if undo { *before } else { *after };
+            }
+        }
+        EditOp::CircleRadius { entity, before, after } => {
+            if let Some(mut circle) = world.get_mut::<EditableCircle>(*entity) {
+                circle.radius = if undo { *before } else { *after };
+            }
+        }
+        EditOp::PolygonPoints { entity, before, after } => {
+            if let Some(mut polygon) = world.get_mut::<EditablePolygon>(*entity) {
+                polygon.points = if undo { before.clone() } else { after.clone() };
+            }
+        }
+        EditOp::Component { entity, type_name, before, after } => {
+            let value = if undo { before } else { after };
+            set_component(world, *entity, type_name, value.as_deref());
+        }
+        EditOp::Spawn { entity, snapshot } if undo => {
+            *snapshot = despawn_with_snapshot(world, *entity);
+        }
+        EditOp::Despawn { entity, snapshot } if !undo => {
+            *snapshot = despawn_with_snapshot(world, *entity);
+        }
+        EditOp::Spawn { snapshot, .. } | EditOp::Despawn { snapshot, .. } => {
+            if let Some(snapshot) = snapshot.take() {
+                return snapshot.spawn(world);
+            }
+        }
+    }
+    Vec::new()
+}
+
+fn set_component(world: &mut World, entity: Entity, type_name: &str, value: Option<&dyn Reflect>) {
+    if world.get_entity(entity).is_none() {
+        return;
+    }
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap().clone();
+    let type_registry = type_registry.read();
+    let rc = type_registry.get_with_name(type_name)
+        .and_then(|reg| reg.data::<ReflectComponent>());
+    let rc = if let Some(rc) = rc {
+        rc
+    } else {
+        error!("History: unknown component {:?}", type_name);
+        return;
+    };
+    match value {
+        // a fresh component, rather than applying onto the old one,
+        // which would keep any extra list elements
+        Some(value) => rc.add_component(world, entity, value),
+        None => rc.remove_component(world, entity),
+    }
+}
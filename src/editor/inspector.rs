@@ -0,0 +1,415 @@
+//! Editor panel for viewing and editing the reflected components of the
+//! active selection
+//!
+//! Only component types that are exported to scenes are shown. Fields with
+//! simple values (numbers, bools, strings, vectors, enums) can be clicked to
+//! type in a new value; Enter applies it. Enums are typed as in scene files
+//! (like `Left`).
+
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::reflect::serde::ReflectDeserializer;
+use bevy::reflect::{ReflectMut, ReflectRef, TypeRegistry};
+use serde::de::DeserializeSeed;
+
+use crate::game::blueprints::ExportableTypes;
+use crate::ui::{UiAssets, UiConfig};
+
+use super::files::file_button;
+use super::history::{EditOp, EditorHistory};
+use super::select::ActiveSelection;
+use super::textinput::{TextInput, TextInputSubmit, TextInputTarget};
+use super::EditorHideCleanup;
+
+/// One editable value of a component
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectorField {
+    /// Short type name of the component
+    pub component: String,
+    /// Field name (or tuple index), optionally followed by `.x`/`.y`/`.z`
+    /// for vectors, or `.angle` for rotations
+    pub path: String,
+    pub value: String,
+}
+
+#[derive(Default)]
+pub struct Inspector {
+    entity: Option<Entity>,
+    fields: Vec<InspectorField>,
+    /// The field whose new value is being typed
+    editing: Option<InspectorField>,
+}
+
+#[derive(Default)]
+pub struct InspectorListParent(Option<Entity>);
+
+/// Index into `Inspector::fields`
+#[derive(Component, Clone, Copy)]
+pub(super) struct InspectorFieldBtn(usize);
+
+pub struct InspectorEditEvent {
+    pub entity: Entity,
+    pub field: InspectorField,
+    pub text: String,
+}
+
+/// Read the fields of all the exportable components of the active selection
+pub(super) fn gather_inspector_fields(world: &mut World) {
+    let entity = world.get_resource::<ActiveSelection>().unwrap().0
+        .filter(|e| world.get_entity(*e).is_some());
+
+    let mut fields = Vec::new();
+    if let Some(e) = entity {
+        let type_registry = world.get_resource::<TypeRegistry>().unwrap().read();
+        let et = world.get_resource::<ExportableTypes>().unwrap();
+        let mut components: Vec<_> = world.entity(e).archetype().components()
+            .filter_map(|id| world.components().get_info(id))
+            .filter_map(|info| type_registry.get(info.type_id()?))
+            .filter(|reg| et.names.contains(reg.short_name()))
+            .collect();
+        components.sort_by(|a, b| a.short_name().cmp(b.short_name()));
+
+        for reg in components {
+            let component = reg.data::<ReflectComponent>()
+                .and_then(|rc| rc.reflect_component(world, e));
+            if let Some(component) = component {
+                read_fields(reg.short_name(), component, &mut fields);
+            }
+        }
+    }
+
+    let mut inspector = world.get_resource_mut::<Inspector>().unwrap();
+    if inspector.entity != entity || inspector.fields != fields {
+        if inspector.entity != entity {
+            inspector.editing = None;
+        }
+        inspector.entity = entity;
+        inspector.fields = fields;
+    }
+}
+
+fn read_fields(component: &str, value: &dyn Reflect, out: &mut Vec<InspectorField>) {
+    let mut push = |path: String, value: String| {
+        out.push(InspectorField {
+            component: component.to_owned(),
+            path,
+            value,
+        });
+    };
+    match value.reflect_ref() {
+        ReflectRef::Struct(s) => {
+            for i in 0..s.field_len() {
+                let name = s.name_at(i).unwrap();
+                read_leaf(name, s.field_at(i).unwrap(), &mut push);
+            }
+        }
+        ReflectRef::TupleStruct(ts) => {
+            for i in 0..ts.field_len() {
+                read_leaf(&i.to_string(), ts.field(i).unwrap(), &mut push);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_leaf(name: &str, value: &dyn Reflect, push: &mut impl FnMut(String, String)) {
+    if let Some(v) = value.downcast_ref::<f32>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<f64>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<i32>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<u32>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<usize>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<bool>() {
+        push(name.to_owned(), v.to_string());
+    } else if let Some(v) = value.downcast_ref::<String>() {
+        push(name.to_owned(), v.clone());
+    } else if let Some(v) = value.downcast_ref::<Vec2>() {
+        push(format!("{}.x", name), v.x.to_string());
+        push(format!("{}.y", name), v.y.to_string());
+    } else if let Some(v) = value.downcast_ref::<Vec3>() {
+        push(format!("{}.x", name), v.x.to_string());
+        push(format!("{}.y", name), v.y.to_string());
+        push(format!("{}.z", name), v.z.to_string());
+    } else if let Some(v) = value.downcast_ref::<Quat>() {
+        // we are 2D; only rotation around Z makes sense
+        let (axis, angle) = v.to_axis_angle();
+        let degrees = (angle * axis.z.signum()).to_degrees();
+        push(format!("{}.angle", name), degrees.to_string());
+    } else if let Some(serializable) = value.serializable() {
+        // other plain values (like enums) are shown and typed as RON
+        if let Ok(text) = ron::to_string(serializable.borrow()) {
+            push(name.to_owned(), text);
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.trim().parse().map_err(|_| format!("{:?} is not a valid value", text))
+}
+
+fn write_field(
+    type_registry: &TypeRegistry,
+    component: &mut dyn Reflect,
+    path: &str,
+    text: &str,
+) -> Result<(), String> {
+    let (name, sub) = match path.split_once('.') {
+        Some((name, sub)) => (name, Some(sub)),
+        None => (path, None),
+    };
+    let value = match component.reflect_mut() {
+        ReflectMut::Struct(s) => s.field_mut(name),
+        ReflectMut::TupleStruct(ts) => match name.parse() {
+            Ok(i) => ts.field_mut(i),
+            Err(_) => None,
+        },
+        _ => None,
+    };
+    let value = value.ok_or_else(|| format!("no field {:?}", name))?;
+    write_leaf(type_registry, value, sub, text)
+}
+
+fn write_leaf(
+    type_registry: &TypeRegistry,
+    value: &mut dyn Reflect,
+    sub: Option<&str>,
+    text: &str,
+) -> Result<(), String> {
+    if let Some(v) = value.downcast_mut::<f32>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<f64>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<i32>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<u32>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<usize>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<bool>() {
+        *v = parse(text)?;
+    } else if let Some(v) = value.downcast_mut::<String>() {
+        *v = text.to_owned();
+    } else if let Some(v) = value.downcast_mut::<Vec2>() {
+        match sub {
+            Some("x") => v.x = parse(text)?,
+            Some("y") => v.y = parse(text)?,
+            _ => return Err("invalid vector component".to_owned()),
+        }
+    } else if let Some(v) = value.downcast_mut::<Vec3>() {
+        match sub {
+            Some("x") => v.x = parse(text)?,
+            Some("y") => v.y = parse(text)?,
+            Some("z") => v.z = parse(text)?,
+            _ => return Err("invalid vector component".to_owned()),
+        }
+    } else if let Some(v) = value.downcast_mut::<Quat>() {
+        let degrees: f32 = parse(text)?;
+        *v = Quat::from_rotation_z(degrees.to_radians());
+    } else if value.serializable().is_some() {
+        // the same form as in scene files
+        let ron_text = format!("{{\"type\": {:?}, \"value\": {}}}", value.type_name(), text);
+        let mut deserializer = ron::de::Deserializer::from_str(&ron_text)
+            .map_err(|e| e.to_string())?;
+        let new_value = ReflectDeserializer::new(type_registry)
+            .deserialize(&mut deserializer)
+            .map_err(|_| format!("{:?} is not a valid value", text))?;
+        value.apply(&*new_value);
+    } else {
+        return Err("unsupported field type".to_owned());
+    }
+    Ok(())
+}
+
+pub(super) fn inspector_btn_handler(
+    In(clicked): In<Option<InspectorFieldBtn>>,
+    mut btn: ResMut<Input<MouseButton>>,
+    mut inspector: ResMut<Inspector>,
+    mut input: ResMut<TextInput>,
+) {
+    let clicked = if let Some(clicked) = clicked {
+        clicked
+    } else {
+        return;
+    };
+    btn.clear_just_pressed(MouseButton::Left);
+    if let Some(field) = inspector.fields.get(clicked.0).cloned() {
+        input.begin(TextInputTarget::InspectorField, field.value.clone());
+        inspector.editing = Some(field);
+    }
+}
+
+pub(super) fn inspector_submit(
+    mut evr: EventReader<TextInputSubmit>,
+    mut evw: EventWriter<InspectorEditEvent>,
+    mut inspector: ResMut<Inspector>,
+) {
+    for ev in evr.iter() {
+        if ev.target != TextInputTarget::InspectorField {
+            continue;
+        }
+        if let (Some(entity), Some(field)) = (inspector.entity, inspector.editing.take()) {
+            evw.send(InspectorEditEvent {
+                entity,
+                field,
+                text: ev.text.clone(),
+            });
+        }
+    }
+}
+
+/// Write edited values into the components
+///
+/// Goes through normal change detection, so the blueprint sync systems
+/// pick the new values up.
+pub(super) fn apply_inspector_edits(world: &mut World) {
+    let events: Vec<InspectorEditEvent> = world
+        .get_resource_mut::<Events<InspectorEditEvent>>().unwrap()
+        .drain()
+        .collect();
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap().clone();
+    let type_registry = type_registry.read();
+
+    let mut ops = Vec::new();
+    for ev in events {
+        let reg = type_registry.get_with_short_name(&ev.field.component);
+        let rc = reg.and_then(|reg| reg.data::<ReflectComponent>());
+        let (reg, rc) = if let (Some(reg), Some(rc)) = (reg, rc) {
+            (reg, rc)
+        } else {
+            error!("Inspector: unknown component {:?}", ev.field.component);
+            continue;
+        };
+        let before = rc.reflect_component(world, ev.entity).map(|c| c.clone_value());
+        if let Some(mut component) = rc.reflect_component_mut(world, ev.entity) {
+            if let Err(e) = write_field(&type_registry, &mut *component, &ev.field.path, &ev.text) {
+                error!("Inspector: cannot set {}.{}: {}", ev.field.component, ev.field.path, e);
+            }
+        }
+        let after = rc.reflect_component(world, ev.entity).map(|c| c.clone_value());
+        ops.extend(EditOp::component(ev.entity, reg.name(), before, after));
+    }
+    world.get_resource_mut::<EditorHistory>().unwrap().push(ops);
+}
+
+/// Rebuild the field buttons when the inspected values change
+pub(super) fn refresh_inspector_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    uiassets: Res<UiAssets>,
+    parent: Res<InspectorListParent>,
+    inspector: Res<Inspector>,
+    input: Res<TextInput>,
+) {
+    if !parent.is_changed() && !inspector.is_changed() && !input.is_changed() {
+        return;
+    }
+    let list = if let Some(list) = parent.0 {
+        list
+    } else {
+        return;
+    };
+
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    cmd.entity(list).despawn_descendants();
+    if inspector.entity.is_none() {
+        return;
+    }
+    let editing = inspector.editing.as_ref()
+        .filter(|_| input.active() == Some(TextInputTarget::InspectorField));
+    let mut prev_component = None;
+    for (i, field) in inspector.fields.iter().enumerate() {
+        if prev_component != Some(&field.component) {
+            let heading = cmd.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    field.component.as_str(),
+                    uicfg.heading_style_text.clone(),
+                    Default::default()
+                ),
+                style: Style {
+                    margin: Rect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }).id();
+            cmd.entity(list).push_children(&[heading]);
+            prev_component = Some(&field.component);
+        }
+        let label = if editing == Some(field) {
+            format!("{}: {}_", field.path, input.buffer)
+        } else {
+            format!("{}: {}", field.path, field.value)
+        };
+        let btn = file_button(&mut cmd, &uicfg, &textstyle_btn, &label, InspectorFieldBtn(i));
+        cmd.entity(list).push_children(&[btn]);
+    }
+}
+
+pub(super) fn spawn_inspector_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    mut r_list: ResMut<InspectorListParent>,
+) {
+    let top = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::rgb(1.0, 1.0, 1.0)),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(20.0),
+                bottom: Val::Auto,
+                left: Val::Px(20.0),
+                right: Val::Auto,
+            },
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(EditorHideCleanup).id();
+
+    let heading_text = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "INSPECTOR:",
+            uicfg.heading_style_text.clone(),
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let list = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Stretch,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    cmd.entity(top).push_children(&[heading_text, list]);
+
+    r_list.0 = Some(list);
+}
+
+pub(super) fn cleanup_inspector_ui(
+    mut r_list: ResMut<InspectorListParent>,
+    mut inspector: ResMut<Inspector>,
+) {
+    r_list.0 = None;
+    inspector.editing = None;
+}
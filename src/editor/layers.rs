@@ -0,0 +1,258 @@
+//! Editor layers: hide or lock everything of a blueprint type
+//!
+//! There is one layer per blueprint type (plus prefab instances), going by
+//! `BlueprintKind`. Hidden entities are not drawn in the editor; hidden and
+//! locked entities can't be selected, so the tools leave them alone.
+//! This only affects the editor; the game always shows everything.
+
+use bevy::prelude::*;
+
+use crate::game::blueprints::{BlueprintKind, BlueprintRegistry};
+use crate::ui::{UiAssets, UiConfig};
+
+use super::EditorHideCleanup;
+use super::files::file_button;
+use super::select::{deselect_entity, ActiveSelection, Selections};
+
+pub struct Layer {
+    pub kind: &'static str,
+    pub hidden: bool,
+    pub locked: bool,
+}
+
+#[derive(Default)]
+pub struct EditorLayers(pub Vec<Layer>);
+
+impl EditorLayers {
+    pub fn add(&mut self, kind: &'static str) {
+        if self.get(kind).is_none() {
+            self.0.push(Layer {
+                kind,
+                hidden: false,
+                locked: false,
+            });
+        }
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&Layer> {
+        self.0.iter().find(|layer| layer.kind == kind)
+    }
+}
+
+/// A layer for every registered blueprint type
+pub(super) fn add_blueprint_layers(
+    mut layers: ResMut<EditorLayers>,
+    registry: Res<BlueprintRegistry>,
+) {
+    for blueprint in registry.blueprints.iter() {
+        layers.add(blueprint.id);
+    }
+}
+
+/// On entities whose layer is hidden
+#[derive(Component)]
+pub struct LayerHidden;
+
+/// On entities whose layer is locked
+#[derive(Component)]
+pub struct LayerLocked;
+
+#[derive(Default)]
+pub struct LayerListParent(Option<Entity>);
+
+#[derive(Component, Clone, Copy)]
+pub(super) enum LayerBtn {
+    ToggleHidden(usize),
+    ToggleLocked(usize),
+}
+
+pub(super) fn layer_btn_handler(
+    In(clicked): In<Option<LayerBtn>>,
+    mut btn: ResMut<Input<MouseButton>>,
+    mut layers: ResMut<EditorLayers>,
+) {
+    let clicked = if let Some(clicked) = clicked {
+        clicked
+    } else {
+        return;
+    };
+    btn.clear_just_pressed(MouseButton::Left);
+    match clicked {
+        LayerBtn::ToggleHidden(i) => {
+            if let Some(layer) = layers.0.get_mut(i) {
+                layer.hidden = !layer.hidden;
+            }
+        }
+        LayerBtn::ToggleLocked(i) => {
+            if let Some(layer) = layers.0.get_mut(i) {
+                layer.locked = !layer.locked;
+            }
+        }
+    }
+}
+
+/// Mark entities according to their layer, when the layers change or new entities appear
+pub(super) fn apply_layers(
+    mut cmd: Commands,
+    layers: Res<EditorLayers>,
+    mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+    q_new: Query<(), Added<BlueprintKind>>,
+    mut q: Query<(Entity, &BlueprintKind, Option<&mut Visibility>)>,
+) {
+    if !layers.is_changed() && q_new.iter().next().is_none() {
+        return;
+    }
+    for (e, kind, vis) in q.iter_mut() {
+        let (hidden, locked) = layers.get(kind.0)
+            .map(|layer| (layer.hidden, layer.locked))
+            .unwrap_or((false, false));
+        if hidden {
+            cmd.entity(e).insert(LayerHidden);
+        } else {
+            cmd.entity(e).remove::<LayerHidden>();
+        }
+        if locked {
+            cmd.entity(e).insert(LayerLocked);
+        } else {
+            cmd.entity(e).remove::<LayerLocked>();
+        }
+        if let Some(mut vis) = vis {
+            if vis.is_visible == hidden {
+                vis.is_visible = !hidden;
+            }
+        }
+        if hidden || locked {
+            deselect_entity(&mut cmd, &mut sels, &mut active, e);
+        }
+    }
+}
+
+/// Hidden entities must still show up in the game
+pub(super) fn set_hidden_visibility<const VIS: bool>(
+    mut q: Query<&mut Visibility, With<LayerHidden>>,
+) {
+    for mut vis in q.iter_mut() {
+        vis.is_visible = VIS;
+    }
+}
+
+pub(super) fn refresh_layer_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    uiassets: Res<UiAssets>,
+    parent: Res<LayerListParent>,
+    layers: Res<EditorLayers>,
+) {
+    if !parent.is_changed() && !layers.is_changed() {
+        return;
+    }
+    let list = if let Some(list) = parent.0 {
+        list
+    } else {
+        return;
+    };
+
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    cmd.entity(list).despawn_descendants();
+    for (i, layer) in layers.0.iter().enumerate() {
+        let label = cmd.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                layer.kind,
+                textstyle_btn.clone(),
+                Default::default()
+            ),
+            style: Style {
+                margin: Rect::all(Val::Px(4.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }).id();
+        let hide = file_button(
+            &mut cmd, &uicfg, &textstyle_btn,
+            if layer.hidden { "Hidden" } else { "Visible" },
+            LayerBtn::ToggleHidden(i),
+        );
+        let lock = file_button(
+            &mut cmd, &uicfg, &textstyle_btn,
+            if layer.locked { "Locked" } else { "Unlocked" },
+            LayerBtn::ToggleLocked(i),
+        );
+
+        let row = cmd.spawn_bundle(NodeBundle {
+            color: UiColor(Color::NONE),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::FlexEnd,
+                ..Default::default()
+            },
+            ..Default::default()
+        }).id();
+        cmd.entity(row).push_children(&[label, hide, lock]);
+        cmd.entity(list).push_children(&[row]);
+    }
+}
+
+pub(super) fn spawn_layer_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    mut r_list: ResMut<LayerListParent>,
+) {
+    let top = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::rgb(1.0, 1.0, 1.0)),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Percent(40.0),
+                bottom: Val::Auto,
+                right: Val::Px(20.0),
+                left: Val::Auto,
+            },
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(EditorHideCleanup).id();
+
+    let heading_text = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "LAYERS:",
+            uicfg.heading_style_text.clone(),
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let list = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Stretch,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    cmd.entity(top).push_children(&[heading_text, list]);
+
+    r_list.0 = Some(list);
+}
+
+pub(super) fn cleanup_layer_ui(mut r_list: ResMut<LayerListParent>) {
+    r_list.0 = None;
+}
@@ -0,0 +1,141 @@
+//! The link tool (K): choose which entities a trigger interacts with
+//!
+//! Click a trigger to pick it, then click other entities to add them to its
+//! `TriggerTargets`, or to remove them if they are already there. Click empty
+//! space (or press Escape) to pick another trigger. Every change can be undone.
+//!
+//! While in the editor, every trigger has arrows drawn to its targets.
+
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+
+use crate::game::environment::{Trigger, TriggerTargets};
+use crate::scene_exporter::stable_id::{EntityRef, StableId};
+use crate::util::WorldCursor;
+
+use super::history::{EditOp, EditorHistory};
+use super::layers::LayerHidden;
+use super::select::{deselect_all, pick_entity, select_entity, ActiveSelection, SelectableQuery, Selections};
+
+const LINK_COLOR: Color = Color::rgba(1.0, 1.0, 0.25, 0.75);
+/// Links of the trigger being edited
+const LINK_ACTIVE_COLOR: Color = Color::rgba(1.0, 0.5, 0.0, 1.0);
+const ARROW_HEAD_SIZE: f32 = 12.0;
+
+/// The trigger whose targets are being edited
+#[derive(Default)]
+pub struct LinkSource(Option<Entity>);
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn mouse_link(
+    mut cmd: Commands,
+    crs: Res<WorldCursor>,
+    kbd: Res<Input<KeyCode>>,
+    mut btn: ResMut<Input<MouseButton>>,
+    imgs: Res<Assets<Image>>,
+    q: SelectableQuery,
+    mut source: ResMut<LinkSource>,
+    mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+    q_trigger: Query<(), With<Trigger>>,
+    q_id: Query<&StableId>,
+    q_targets: Query<&TriggerTargets>,
+    mut history: ResMut<EditorHistory>,
+) {
+    if kbd.just_pressed(KeyCode::Escape) {
+        source.0 = None;
+        deselect_all(&mut cmd, &mut sels, &mut active);
+        return;
+    }
+    if !btn.just_pressed(MouseButton::Left) {
+        return;
+    }
+    btn.clear_just_pressed(MouseButton::Left);
+
+    let (clicked, sz) = if let Some(picked) = pick_entity(crs.0, &q, &imgs) {
+        picked
+    } else {
+        source.0 = None;
+        deselect_all(&mut cmd, &mut sels, &mut active);
+        return;
+    };
+
+    let current = source.0;
+    let trigger = match current {
+        // the trigger itself might be gone
+        Some(trigger) if q_trigger.get(trigger).is_ok() && trigger != clicked => trigger,
+        _ => {
+            if q_trigger.get(clicked).is_err() {
+                info!("Pick a trigger to link from first");
+                return;
+            }
+            source.0 = Some(clicked);
+            deselect_all(&mut cmd, &mut sels, &mut active);
+            select_entity(&mut cmd, &mut sels, &mut active, clicked, sz);
+            return;
+        }
+    };
+
+    let id = if let Ok(id) = q_id.get(clicked) {
+        *id
+    } else {
+        info!("Cannot link to {:?}: it is not saved with the scene", clicked);
+        return;
+    };
+    let before = q_targets.get(trigger).ok().cloned();
+    let mut after = before.clone().unwrap_or_default();
+    if let Some(i) = after.targets.iter().position(|t| t.id() == id) {
+        after.targets.remove(i);
+    } else {
+        after.targets.push(EntityRef::new(id, clicked));
+    }
+    history.push(vec![EditOp::Component {
+        entity: trigger,
+        type_name: std::any::type_name::<TriggerTargets>().to_owned(),
+        before: before.map(|t| Box::new(t) as Box<dyn Reflect>),
+        after: Some(Box::new(after.clone())),
+    }]);
+    cmd.entity(trigger).insert(after);
+}
+
+pub(super) fn clear_link_source(mut source: ResMut<LinkSource>) {
+    source.0 = None;
+}
+
+fn draw_arrow(lines: &mut DebugLines, from: Vec2, to: Vec2, color: Color) {
+    let z = 999.0;
+    let dir = (to - from).normalize_or_zero();
+    if dir == Vec2::ZERO {
+        return;
+    }
+    lines.line_colored(from.extend(z), to.extend(z), 0.0, color);
+    for side in [dir.perp(), -dir.perp()] {
+        let head = to - (dir + side * 0.5) * ARROW_HEAD_SIZE;
+        lines.line_colored(to.extend(z), head.extend(z), 0.0, color);
+    }
+}
+
+/// Arrows from every trigger to what it interacts with
+pub(super) fn draw_links(
+    source: Res<LinkSource>,
+    mut lines: ResMut<DebugLines>,
+    q_trigger: Query<(Entity, &GlobalTransform, &Trigger, Option<&TriggerTargets>), Without<LayerHidden>>,
+    q_target: Query<&GlobalTransform>,
+) {
+    for (e, gxf, trigger, targets) in q_trigger.iter() {
+        let color = if source.0 == Some(e) { LINK_ACTIVE_COLOR } else { LINK_COLOR };
+        let from = gxf.translation.truncate();
+        let saved = targets.iter()
+            .flat_map(|t| t.targets.iter())
+            .filter_map(|t| t.entity());
+        for target in trigger.entities.iter().copied().chain(saved) {
+            // like pickups, which are their own trigger
+            if target == e {
+                continue;
+            }
+            if let Ok(target_xf) = q_target.get(target) {
+                draw_arrow(&mut lines, from, target_xf.translation.truncate(), color);
+            }
+        }
+    }
+}
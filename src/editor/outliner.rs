@@ -0,0 +1,283 @@
+//! Editor panel listing every editable entity
+//!
+//! Click an entry to select it (Shift to add to the selection),
+//! double-click to move the camera to it. Scroll with the mouse wheel
+//! while hovering the panel. The filter button cycles through the
+//! blueprint types (the same ones as the layers panel).
+
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::ui::FocusPolicy;
+
+use crate::game::blueprints::BlueprintKind;
+use crate::ui::{UiAssets, UiConfig};
+
+use super::camera::EditorCameraTarget;
+use super::files::file_button;
+use super::layers::{EditorLayers, LayerHidden, LayerLocked};
+use super::select::{deselect_all, ActiveSelection, PendingSelect, Selections};
+use super::{Editable, EditorHideCleanup};
+
+/// How many entries are shown at once
+const PAGE_SIZE: usize = 12;
+/// Entries scrolled per mouse wheel step
+const SCROLL_STEP: usize = 3;
+/// Max seconds between the clicks of a double-click
+const DOUBLE_CLICK_TIME: f64 = 0.4;
+const SELECTED_COLOR: Color = Color::rgb(0.55, 0.55, 0.55);
+
+#[derive(Default)]
+pub struct Outliner {
+    /// Index of the first entry shown
+    offset: usize,
+    /// Only list entities of this blueprint type
+    filter: Option<&'static str>,
+}
+
+impl Outliner {
+    fn shows(&self, kind: Option<&BlueprintKind>) -> bool {
+        match self.filter {
+            Some(filter) => kind.map(|k| k.0) == Some(filter),
+            None => true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct OutlinerListParent(Option<Entity>);
+
+/// The whole panel; the mouse wheel scrolls the list while it is hovered
+#[derive(Component)]
+pub(super) struct OutlinerPanel;
+
+#[derive(Component, Clone, Copy)]
+pub(super) enum OutlinerBtn {
+    Entry(Entity),
+    CycleFilter,
+}
+
+type OutlinerQuery<'w, 's> = Query<'w, 's,
+    (Entity, &'static GlobalTransform, Option<&'static BlueprintKind>),
+    With<Editable>,
+>;
+
+/// The listed entities, in a stable order
+fn outliner_entries(outliner: &Outliner, q: &OutlinerQuery) -> Vec<(Entity, Vec2, &'static str)> {
+    let mut entries: Vec<_> = q.iter()
+        .filter(|(_, _, kind)| outliner.shows(*kind))
+        .map(|(e, xf, kind)| (e, xf.translation.truncate(), kind.map(|k| k.0).unwrap_or("Entity")))
+        .collect();
+    entries.sort_by(|a, b| a.2.cmp(b.2).then(a.0.id().cmp(&b.0.id())));
+    entries
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn outliner_btn_handler(
+    In(clicked): In<Option<OutlinerBtn>>,
+    mut cmd: Commands,
+    mut btn: ResMut<Input<MouseButton>>,
+    kbd: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    layers: Res<EditorLayers>,
+    mut outliner: ResMut<Outliner>,
+    mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+    mut last_click: Local<Option<(Entity, f64)>>,
+    q_target: Query<(&GlobalTransform, Option<&LayerHidden>, Option<&LayerLocked>)>,
+    mut cam_target: ResMut<EditorCameraTarget>,
+) {
+    let clicked = if let Some(clicked) = clicked {
+        clicked
+    } else {
+        return;
+    };
+    btn.clear_just_pressed(MouseButton::Left);
+    match clicked {
+        OutlinerBtn::Entry(e) => {
+            let (target_xf, hidden, locked) = if let Ok(target) = q_target.get(e) {
+                target
+            } else {
+                return;
+            };
+            let now = time.seconds_since_startup();
+            let double = matches!(*last_click, Some((prev, t)) if prev == e && now - t < DOUBLE_CLICK_TIME);
+            *last_click = Some((e, now));
+
+            if double {
+                cam_target.translation = target_xf.translation.truncate();
+                return;
+            }
+            if hidden.is_some() || locked.is_some() {
+                info!("Cannot select {:?}: its layer is hidden or locked", e);
+                return;
+            }
+            let shift = kbd.pressed(KeyCode::LShift) || kbd.pressed(KeyCode::RShift);
+            if !shift {
+                deselect_all(&mut cmd, &mut sels, &mut active);
+            }
+            cmd.entity(e).insert(PendingSelect);
+        }
+        OutlinerBtn::CycleFilter => {
+            // All -> each type in turn -> All
+            let next = match outliner.filter {
+                None => layers.0.first(),
+                Some(filter) => layers.0.iter().skip_while(|layer| layer.kind != filter).nth(1),
+            };
+            outliner.filter = next.map(|layer| layer.kind);
+            outliner.offset = 0;
+        }
+    }
+}
+
+pub(super) fn outliner_scroll(
+    mut evr: EventReader<MouseWheel>,
+    mut outliner: ResMut<Outliner>,
+    q_panel: Query<&Interaction, With<OutlinerPanel>>,
+    q: OutlinerQuery,
+) {
+    let hovered = outliner_hovered(&q_panel);
+    let mut offset = outliner.offset;
+    for ev in evr.iter() {
+        if !hovered {
+            continue;
+        }
+        if ev.y > 0.0 {
+            offset = offset.saturating_sub(SCROLL_STEP);
+        } else if ev.y < 0.0 {
+            offset += SCROLL_STEP;
+        }
+    }
+    let count = q.iter().filter(|(_, _, kind)| outliner.shows(*kind)).count();
+    offset = offset.min(count.saturating_sub(PAGE_SIZE));
+    if offset != outliner.offset {
+        outliner.offset = offset;
+    }
+}
+
+/// Is the mouse over the outliner? (so it scrolls instead of zooming)
+pub(super) fn outliner_hovered(q_panel: &Query<&Interaction, With<OutlinerPanel>>) -> bool {
+    q_panel.iter().any(|i| *i != Interaction::None)
+}
+
+/// Rebuild the list when entities come, go or move, or the selection changes
+#[allow(clippy::too_many_arguments)]
+pub(super) fn refresh_outliner_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    uiassets: Res<UiAssets>,
+    parent: Res<OutlinerListParent>,
+    outliner: Res<Outliner>,
+    sels: Res<Selections>,
+    removed: RemovedComponents<Editable>,
+    q_changed: Query<(), (With<Editable>, Changed<GlobalTransform>)>,
+    q: OutlinerQuery,
+) {
+    let changed = parent.is_changed() || outliner.is_changed() || sels.is_changed()
+        || removed.iter().next().is_some()
+        || q_changed.iter().next().is_some();
+    if !changed {
+        return;
+    }
+    let list = if let Some(list) = parent.0 {
+        list
+    } else {
+        return;
+    };
+
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    cmd.entity(list).despawn_descendants();
+
+    let filter_label = format!("Filter: {}", outliner.filter.unwrap_or("All"));
+    let filter_btn = file_button(&mut cmd, &uicfg, &textstyle_btn, &filter_label, OutlinerBtn::CycleFilter);
+    cmd.entity(list).push_children(&[filter_btn]);
+
+    let entries = outliner_entries(&outliner, &q);
+    for (e, pos, kind) in entries.iter().skip(outliner.offset).take(PAGE_SIZE) {
+        let label = format!("{} ({:.0}, {:.0})", kind, pos.x, pos.y);
+        let btn = file_button(&mut cmd, &uicfg, &textstyle_btn, &label, OutlinerBtn::Entry(*e));
+        // let the wheel through to the panel
+        cmd.entity(btn).insert(FocusPolicy::Pass);
+        if sels.0.contains_key(e) {
+            cmd.entity(btn).insert(UiColor(SELECTED_COLOR));
+        }
+        cmd.entity(list).push_children(&[btn]);
+    }
+
+    let shown_to = (outliner.offset + PAGE_SIZE).min(entries.len());
+    let range = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            format!("{}-{} of {}", (outliner.offset + 1).min(shown_to), shown_to, entries.len()),
+            textstyle_btn,
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+    cmd.entity(list).push_children(&[range]);
+}
+
+pub(super) fn spawn_outliner_ui(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    mut r_list: ResMut<OutlinerListParent>,
+) {
+    let top = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::rgb(1.0, 1.0, 1.0)),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Percent(40.0),
+                bottom: Val::Auto,
+                left: Val::Px(20.0),
+                right: Val::Auto,
+            },
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(Interaction::default()).insert(OutlinerPanel).insert(EditorHideCleanup).id();
+
+    let heading_text = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "OUTLINER:",
+            uicfg.heading_style_text.clone(),
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let list = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Stretch,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(FocusPolicy::Pass).id();
+
+    cmd.entity(top).push_children(&[heading_text, list]);
+
+    r_list.0 = Some(list);
+}
+
+pub(super) fn cleanup_outliner_ui(mut r_list: ResMut<OutlinerListParent>) {
+    r_list.0 = None;
+}
@@ -0,0 +1,107 @@
+//! Play from here: try out the scene being edited, starting at the cursor
+//!
+//! F6 takes an in-memory snapshot of the scene and of the run in progress,
+//! and goes back to the game with the player at the cursor, with fresh
+//! weapons and timer. Going back into the editor restores the snapshot,
+//! undoing whatever happened during the test (pickups used up, enemies
+//! spawned or killed, ...). The save game is left alone during the test;
+//! quitting to the main menu throws the snapshot away.
+
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::scene::DynamicScene;
+
+use crate::AppState;
+use crate::game::GameTimer;
+use crate::game::damage::Health;
+use crate::game::player::Player;
+use crate::game::savegame::{capture_savegame, restore_savegame, SaveGame, SavingSuspended};
+use crate::game::shooting::WeaponryBundle;
+use crate::scene_exporter::{restore_scene, snapshot_scene};
+use crate::util::WorldCursor;
+
+use super::ToolState;
+use super::history::EditorHistory;
+
+/// Present while testing with "play from here"
+pub struct PlaytestSnapshot {
+    scene: DynamicScene,
+    save: SaveGame,
+}
+
+/// Start testing with the player at the given position
+pub struct PlayFromHereEvent(pub Vec2);
+
+/// F6: play from the cursor position
+pub(super) fn play_from_here_hotkey(
+    kbd: Res<Input<KeyCode>>,
+    crs: Res<WorldCursor>,
+    toolstate: Res<State<ToolState>>,
+    mut evw: EventWriter<PlayFromHereEvent>,
+) {
+    // not while something is still being placed
+    if !matches!(toolstate.current(), ToolState::Using(_)) {
+        return;
+    }
+    if kbd.just_pressed(KeyCode::F6) {
+        evw.send(PlayFromHereEvent(crs.0));
+    }
+}
+
+pub(super) fn begin_playtest(world: &mut World) {
+    let pos = world.get_resource_mut::<Events<PlayFromHereEvent>>().unwrap()
+        .drain()
+        .last()
+        .unwrap()
+        .0;
+
+    let player = world.query_filtered::<Entity, With<Player>>().iter(world).next();
+    let player = match player {
+        Some(player) => player,
+        None => {
+            error!("Cannot play from here: no game is running under the editor");
+            return;
+        }
+    };
+
+    let save = match capture_savegame(world) {
+        Some(save) => save,
+        None => return,
+    };
+    let scene = snapshot_scene(world);
+    world.insert_resource(PlaytestSnapshot { scene, save });
+    // dying or quitting during the test must not touch the real save game
+    world.insert_resource(SavingSuspended);
+
+    let mut emut = world.entity_mut(player);
+    if let Some(mut xf) = emut.get_mut::<Transform>() {
+        xf.translation.x = pos.x;
+        xf.translation.y = pos.y;
+    }
+    if let Some(mut health) = emut.get_mut::<Health>() {
+        health.current = health.max;
+    }
+    emut.insert_bundle(WeaponryBundle::default());
+    world.get_resource_mut::<GameTimer>().unwrap().0.reset();
+
+    // back to the game, like F12
+    if world.get_resource_mut::<State<AppState>>().unwrap().pop().is_ok() {
+        world.get_resource_mut::<State<ToolState>>().unwrap().pop().ok();
+    }
+    info!("Playing from {}", pos);
+}
+
+/// When coming back to the editor, put everything back the way it was before the test
+pub(super) fn end_playtest(world: &mut World) {
+    let snapshot = match world.remove_resource::<PlaytestSnapshot>() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    world.remove_resource::<SavingSuspended>();
+
+    restore_scene(world, &snapshot.scene);
+    restore_savegame(world, snapshot.save);
+    // the entities it refers to are gone
+    world.get_resource_mut::<EditorHistory>().unwrap().clear();
+    info!("Play from here: restored the scene");
+}
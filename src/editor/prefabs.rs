@@ -0,0 +1,155 @@
+//! Editor ui for placing prefab instances and saving new prefabs
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::scene_exporter::prefab::{self, PrefabChild, PrefabInstance, PrefabSavedEvent, SavePrefabEvent};
+use crate::ui::{UiAssets, UiConfig};
+use crate::util::WorldCursor;
+
+use super::files::file_button;
+use super::select::Selections;
+use super::textinput::{TextInput, TextInputSubmit, TextInputTarget};
+use super::collider::EditorIcon;
+use super::{Editable, NewlySpawned, ToolState};
+
+const PREFAB_ICON_COLOR: Color = Color::rgba(0.75, 0.25, 1.0, 0.5);
+const PREFAB_ICON_SIZE: f32 = 24.0;
+
+#[derive(Default)]
+pub struct PrefabListParent(pub Option<Entity>);
+
+#[derive(Component, Clone)]
+pub(super) enum PrefabBtn {
+    Place(PathBuf),
+    SaveSelection,
+}
+
+/// Make prefab instances selectable, and visible even when they are empty
+pub(super) fn init_prefab_instances(
+    mut commands: Commands,
+    q: Query<Entity, Added<PrefabInstance>>,
+) {
+    for e in q.iter() {
+        commands.entity(e)
+            .insert(Editable)
+            .insert(EditorIcon {
+                color: PREFAB_ICON_COLOR,
+                size: Vec2::splat(PREFAB_ICON_SIZE),
+            });
+    }
+}
+
+pub(super) fn prefab_btn_handler(
+    In(clicked): In<Option<PrefabBtn>>,
+    mut commands: Commands,
+    mut btn: ResMut<Input<MouseButton>>,
+    mut toolstate: ResMut<State<ToolState>>,
+    mut input: ResMut<TextInput>,
+    crs: Res<WorldCursor>,
+) {
+    let clicked = if let Some(clicked) = clicked {
+        clicked
+    } else {
+        return;
+    };
+    btn.clear_just_pressed(MouseButton::Left);
+    match clicked {
+        PrefabBtn::Place(path) => {
+            toolstate.set(ToolState::Spawning).ok();
+            commands.spawn()
+                .insert(PrefabInstance {
+                    path: path.to_string_lossy().into_owned(),
+                    linked: true,
+                })
+                .insert(Transform::from_translation(crs.0.extend(0.0)))
+                .insert(NewlySpawned);
+        }
+        PrefabBtn::SaveSelection => {
+            input.begin(TextInputTarget::SavePrefab, "");
+        }
+    }
+}
+
+pub(super) fn save_prefab_submit(
+    mut evr: EventReader<TextInputSubmit>,
+    mut evw: EventWriter<SavePrefabEvent>,
+    sels: Res<Selections>,
+) {
+    for ev in evr.iter() {
+        if ev.target != TextInputTarget::SavePrefab {
+            continue;
+        }
+        let mut name = ev.text.trim().to_owned();
+        if name.is_empty() {
+            continue;
+        }
+        if !name.ends_with(".scn.ron") {
+            name.push_str(".scn.ron");
+        }
+        evw.send(SavePrefabEvent {
+            path: PathBuf::from(prefab::PREFAB_DIR).join(name),
+            entities: sels.0.keys().copied().collect(),
+        });
+    }
+}
+
+/// L: link/unlink the selected prefab instances
+pub(super) fn keyboard_toggle_prefab_link(
+    mut commands: Commands,
+    kbd: Res<Input<KeyCode>>,
+    sels: Res<Selections>,
+    mut q_instance: Query<&mut PrefabInstance>,
+    q_children: Query<(Entity, &PrefabChild)>,
+) {
+    if !kbd.just_pressed(KeyCode::L) {
+        return;
+    }
+    for e in sels.0.keys() {
+        if let Ok(mut instance) = q_instance.get_mut(*e) {
+            let linked = !instance.linked;
+            prefab::set_prefab_linked(&mut commands, *e, &mut instance, linked, &q_children);
+            info!("Prefab instance {:?} is now {}", e, if linked { "linked" } else { "unlinked" });
+        }
+    }
+}
+
+/// Rebuild the prefab buttons whenever we might have a new prefab
+pub(super) fn refresh_prefab_list(
+    mut cmd: Commands,
+    uicfg: Res<UiConfig>,
+    uiassets: Res<UiAssets>,
+    parent: Res<PrefabListParent>,
+    mut evr_saved: EventReader<PrefabSavedEvent>,
+) {
+    let saved = evr_saved.iter().next().is_some();
+    if !parent.is_changed() && !saved {
+        return;
+    }
+    let list = if let Some(list) = parent.0 {
+        list
+    } else {
+        return;
+    };
+
+    let textstyle_btn = TextStyle {
+        color: Color::BLACK,
+        font_size: 16.0,
+        font: uiassets.font_menu_regular.clone(),
+    };
+
+    cmd.entity(list).despawn_descendants();
+    let mut btns = vec![
+        file_button(&mut cmd, &uicfg, &textstyle_btn, "Save Selection", PrefabBtn::SaveSelection),
+    ];
+    for file in prefab::find_prefab_files() {
+        let label = prefab::prefab_name(&file);
+        btns.push(file_button(&mut cmd, &uicfg, &textstyle_btn, &label, PrefabBtn::Place(file)));
+    }
+    cmd.entity(list).push_children(&btns);
+}
+
+pub(super) fn cleanup_prefab_ui(mut r_list: ResMut<PrefabListParent>) {
+    r_list.0 = None;
+}
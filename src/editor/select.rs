@@ -1,16 +1,26 @@
 use bevy::prelude::*;
 use bevy::utils::HashMap;
+use bevy_prototype_debug_lines::DebugLines;
 use heron::CollisionShape;
 
-use crate::{util::{WorldCursor, WorldCursorPrev}, scene_exporter::SaveSceneMarker, game::{blueprints::BasicBlueprintBundle, collider::ColliderKind}};
+use crate::{util::WorldCursor, game::blueprints::BlueprintKind};
 
-use super::{UsingTool, NewlySpawned, collider::EditableCollider, Editable, ToolState};
+use super::{NewlySpawned, Editable};
+use super::collider::{EditableCircle, EditablePolygon};
+use super::history::DespawnEntitiesEvent;
+use super::layers::{LayerHidden, LayerLocked};
 
 const SELECTION_COLOR: Color = Color::rgba(1.0, 0.0, 1.0, 0.5);
 
 /// Map of (Target Entity) -> (Selection Entity)
 #[derive(Default)]
-pub struct Selections(HashMap<Entity, Entity>);
+pub struct Selections(pub HashMap<Entity, Entity>);
+
+/// The most recently selected entity
+///
+/// When parenting, the other selected entities are attached to this one.
+#[derive(Default)]
+pub struct ActiveSelection(pub Option<Entity>);
 
 #[derive(Component)]
 pub struct Selection(pub Entity);
@@ -42,12 +52,19 @@ pub fn keyboard_despawn_selected(
     mut cmd: Commands,
     kbd: Res<Input<KeyCode>>,
     q_sel: Query<(Entity, &Selection)>,
+    mut sels: ResMut<Selections>,
+    mut evw: EventWriter<DespawnEntitiesEvent>,
 ) {
     if kbd.just_pressed(KeyCode::Delete) || kbd.just_pressed(KeyCode::Back) {
+        let mut targets = Vec::new();
         for (e, sel) in q_sel.iter() {
-            cmd.entity(sel.0).despawn_recursive();
+            targets.push(sel.0);
+            sels.0.remove(&sel.0);
             cmd.entity(e).despawn_recursive();
         }
+        if !targets.is_empty() {
+            evw.send(DespawnEntitiesEvent(targets));
+        }
     }
 }
 
@@ -65,74 +82,397 @@ pub fn keyboard_deselect_all(
     }
 }
 
+/// How a click or marquee changes the selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelectMode {
+    /// Select only what was clicked
+    Replace,
+    /// Shift: add to the selection
+    Add,
+    /// Ctrl: remove from the selection
+    Subtract,
+}
+
+impl SelectMode {
+    fn from_keys(kbd: &Input<KeyCode>) -> Self {
+        if kbd.pressed(KeyCode::LControl) || kbd.pressed(KeyCode::RControl) {
+            SelectMode::Subtract
+        } else if kbd.pressed(KeyCode::LShift) || kbd.pressed(KeyCode::RShift) {
+            SelectMode::Add
+        } else {
+            SelectMode::Replace
+        }
+    }
+}
+
+/// Where a rectangle drag-selection started, if one is in progress
+#[derive(Default)]
+pub struct Marquee(Option<Vec2>);
+
+/// Don't treat clicks on empty space as a marquee drag, unless the cursor moves this far
+const MARQUEE_MIN_SIZE: f32 = 4.0;
+const MARQUEE_COLOR: Color = Color::rgba(1.0, 0.0, 1.0, 1.0);
+
+pub(super) type SelectableQuery<'w, 's> = Query<'w, 's, (
+    Entity,
+    &'static GlobalTransform,
+    Option<&'static Sprite>,
+    Option<&'static Handle<Image>>,
+    Option<&'static CollisionShape>,
+    Option<&'static EditableCircle>,
+    Option<&'static EditablePolygon>,
+), (
+    Without<NewlySpawned>,
+    Without<LayerHidden>,
+    Without<LayerLocked>,
+    With<Editable>,
+    Or<(With<Sprite>, With<CollisionShape>, With<EditableCircle>, With<EditablePolygon>)>
+)>;
+
+/// The clickable area of an entity, in its local space
+enum PickShape {
+    Box { half_size: Vec2 },
+    Circle { radius: f32 },
+    /// Convex
+    Polygon { points: Vec<Vec2> },
+}
+
+impl PickShape {
+    fn new(
+        spr: Option<&Sprite>,
+        h_img: Option<&Handle<Image>>,
+        shape: Option<&CollisionShape>,
+        circle: Option<&EditableCircle>,
+        polygon: Option<&EditablePolygon>,
+        imgs: &Assets<Image>,
+    ) -> Option<Self> {
+        // the editable shapes are what the collider is made from
+        if let Some(circle) = circle {
+            Some(PickShape::Circle { radius: circle.radius })
+        } else if let Some(polygon) = polygon {
+            Some(PickShape::Polygon { points: polygon.points.clone() })
+        } else if let Some(shape) = shape {
+            match shape {
+                CollisionShape::Cuboid { half_extends, .. } => Some(PickShape::Box {
+                    half_size: half_extends.truncate(),
+                }),
+                CollisionShape::Sphere { radius } => Some(PickShape::Circle { radius: *radius }),
+                CollisionShape::ConvexHull { points, .. } => Some(PickShape::Box {
+                    half_size: points.iter().fold(Vec2::ZERO, |acc, p| acc.max(p.truncate().abs())),
+                }),
+                _ => None,
+            }
+        } else if let Some(spr) = spr {
+            let size = spr.custom_size
+                .or_else(|| {
+                    h_img.and_then(|h_img|
+                        imgs.get(h_img)
+                            .map(|img| {
+                                let isz = img.texture_descriptor.size;
+                                Vec2::new(isz.width as f32, isz.height as f32)
+                            }))
+                }).unwrap_or(Vec2::new(2.0, 2.0));
+            Some(PickShape::Box { half_size: size / 2.0 })
+        } else {
+            None
+        }
+    }
+
+    fn contains(&self, pos: Vec2) -> bool {
+        match self {
+            PickShape::Box { half_size } => {
+                pos.x > -half_size.x && pos.x < half_size.x &&
+                pos.y > -half_size.y && pos.y < half_size.y
+            }
+            PickShape::Circle { radius } => pos.length() < *radius,
+            PickShape::Polygon { points } => {
+                if points.len() < 3 {
+                    return false;
+                }
+                // on the same side of every edge, whichever way the points wind
+                let mut sign = 0.0;
+                for i in 0..points.len() {
+                    let a = points[i];
+                    let b = points[(i + 1) % points.len()];
+                    let cross = (b - a).perp_dot(pos - a);
+                    if cross == 0.0 {
+                        continue;
+                    }
+                    if sign == 0.0 {
+                        sign = cross.signum();
+                    } else if cross.signum() != sign {
+                        return false;
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// Size of the selection box to show around it
+    fn size(&self) -> Vec2 {
+        match self {
+            PickShape::Box { half_size } => *half_size * 2.0,
+            PickShape::Circle { radius } => Vec2::splat(*radius * 2.0),
+            PickShape::Polygon { points } => {
+                points.iter().fold(Vec2::ZERO, |acc, p| acc.max(p.abs())) * 2.0
+            }
+        }
+    }
+}
+
+/// The topmost selectable entity at the given position, and the size of its selection box
+pub(super) fn pick_entity(pos: Vec2, q: &SelectableQuery, imgs: &Assets<Image>) -> Option<(Entity, Vec2)> {
+    let mut best = None;
+    for (e, xf, spr, h_img, shape, circle, polygon) in q.iter() {
+        let minv = xf.compute_matrix().inverse();
+        let pos_model = minv.transform_point3(pos.extend(xf.translation.z));
+
+        let pick = if let Some(pick) = PickShape::new(spr, h_img, shape, circle, polygon, imgs) {
+            pick
+        } else {
+            continue;
+        };
+
+        if pick.contains(pos_model.truncate()) {
+            if let Some((z, _, _)) = best {
+                if xf.translation.z > z {
+                    best = Some((xf.translation.z, e, pick.size()));
+                }
+            } else {
+                best = Some((xf.translation.z, e, pick.size()));
+            }
+        }
+    }
+    best.map(|(_, e, sz)| (e, sz))
+}
+
+pub(super) fn select_entity(
+    cmd: &mut Commands,
+    sels: &mut Selections,
+    active: &mut ActiveSelection,
+    e: Entity,
+    sz: Vec2,
+) {
+    if !sels.0.contains_key(&e) {
+        let sel = cmd.spawn_bundle(SelectionBundle::new(e, sz)).id();
+        sels.0.insert(e, sel);
+    }
+    active.0 = Some(e);
+}
+
+pub(super) fn deselect_entity(
+    cmd: &mut Commands,
+    sels: &mut Selections,
+    active: &mut ActiveSelection,
+    e: Entity,
+) {
+    if let Some(sel) = sels.0.remove(&e) {
+        cmd.entity(sel).despawn_recursive();
+    }
+    if active.0 == Some(e) {
+        active.0 = None;
+    }
+}
+
+pub(super) fn deselect_all(cmd: &mut Commands, sels: &mut Selections, active: &mut ActiveSelection) {
+    for (_, sel) in sels.0.drain() {
+        cmd.entity(sel).despawn_recursive();
+    }
+    active.0 = None;
+}
+
+/// Click: select what is under the cursor
+/// Drag from empty space: select everything inside the rectangle
+///
+/// Hold Shift to add to the selection, Ctrl to remove from it.
 pub fn mouse_select(
     crs: Res<WorldCursor>,
+    kbd: Res<Input<KeyCode>>,
     mut btn: ResMut<Input<MouseButton>>,
-    q: Query<(
-        Entity,
-        &GlobalTransform,
-        Option<&Sprite>,
-        Option<&Handle<Image>>,
-        Option<&CollisionShape>
-    ), (
-        Without<NewlySpawned>,
-        Without<Parent>,
-        With<Editable>,
-        Or<(With<Sprite>, With<CollisionShape>)>
-    )>,
+    q: SelectableQuery,
     imgs: Res<Assets<Image>>,
     mut cmd: Commands,
     mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+    mut marquee: ResMut<Marquee>,
+    mut lines: ResMut<DebugLines>,
 ) {
+    let mode = SelectMode::from_keys(&kbd);
+
     if btn.just_pressed(MouseButton::Left) {
         btn.clear_just_pressed(MouseButton::Left);
-        let mut best = None;
-        for (e, xf, spr, h_img, shape) in q.iter() {
-            //dbg!(best);
-            let minv = xf.compute_matrix().inverse();
-            let pos_model = minv.transform_point3(crs.0.extend(xf.translation.z));
-            //dbg!(pos_model);
-
-            let spr_sz = if let Some(shape) = shape {
-                match shape {
-                    CollisionShape::Cuboid { half_extends, .. } => half_extends.truncate(),
-                    _ => continue,
-                }
-            } else if let Some(spr) = spr {
-                spr.custom_size
-                    .or_else(|| {
-                        h_img.and_then(|h_img|
-                            imgs.get(h_img)
-                                .map(|img| {
-                                    let isz = img.texture_descriptor.size;
-                                    Vec2::new(isz.width as f32, isz.height as f32)
-                                }))
-                    }).unwrap_or(Vec2::new(2.0, 2.0)) / 2.0
-            } else {
-                continue;
-            };
-
-            if pos_model.x > -spr_sz.x && pos_model.x < spr_sz.x &&
-               pos_model.y > -spr_sz.y && pos_model.y < spr_sz.y
-            {
-                if let Some((z, _, _)) = best {
-                    if xf.translation.z > z {
-                        best = Some((xf.translation.z, e, spr_sz * 2.0));
-                    }
-                } else {
-                    best = Some((xf.translation.z, e, spr_sz * 2.0));
+        if let Some((e, sz)) = pick_entity(crs.0, &q, &imgs) {
+            match mode {
+                SelectMode::Replace => {
+                    deselect_all(&mut cmd, &mut sels, &mut active);
+                    select_entity(&mut cmd, &mut sels, &mut active, e, sz);
                 }
+                SelectMode::Add => select_entity(&mut cmd, &mut sels, &mut active, e, sz),
+                SelectMode::Subtract => deselect_entity(&mut cmd, &mut sels, &mut active, e),
             }
+        } else {
+            marquee.0 = Some(crs.0);
         }
+    }
 
-        if let Some((_, e, sz)) = best {
-            if sels.0.contains_key(&e) {
-                let sel = sels.0.remove(&e).unwrap();
-                cmd.entity(sel).despawn_recursive();
-            } else {
-                let sel = cmd.spawn_bundle(SelectionBundle::new(e, sz)).id();
-                sels.0.insert(e, sel);
+    let start = if let Some(start) = marquee.0 {
+        start
+    } else {
+        return;
+    };
+    let min = start.min(crs.0);
+    let max = start.max(crs.0);
+
+    if btn.pressed(MouseButton::Left) {
+        let z = 999.0;
+        let corners = [
+            Vec3::new(min.x, min.y, z),
+            Vec3::new(max.x, min.y, z),
+            Vec3::new(max.x, max.y, z),
+            Vec3::new(min.x, max.y, z),
+        ];
+        for i in 0..4 {
+            lines.line_colored(corners[i], corners[(i + 1) % 4], 0.0, MARQUEE_COLOR);
+        }
+        return;
+    }
+
+    // released
+    marquee.0 = None;
+    if mode == SelectMode::Replace {
+        deselect_all(&mut cmd, &mut sels, &mut active);
+    }
+    if (max - min).max_element() < MARQUEE_MIN_SIZE {
+        return;
+    }
+    for (e, xf, spr, h_img, shape, circle, polygon) in q.iter() {
+        let pos = xf.translation.truncate();
+        if pos.x < min.x || pos.x > max.x || pos.y < min.y || pos.y > max.y {
+            continue;
+        }
+        let sz = if let Some(pick) = PickShape::new(spr, h_img, shape, circle, polygon, &imgs) {
+            pick.size()
+        } else {
+            continue;
+        };
+        if mode == SelectMode::Subtract {
+            deselect_entity(&mut cmd, &mut sels, &mut active, e);
+        } else {
+            select_entity(&mut cmd, &mut sels, &mut active, e, sz);
+        }
+    }
+}
+
+/// Select this entity as soon as it can be (once it has a sprite or collider)
+#[derive(Component)]
+pub struct PendingSelect;
+
+pub(super) fn select_pending(
+    mut cmd: Commands,
+    q: SelectableQuery,
+    q_pending: Query<Entity, With<PendingSelect>>,
+    imgs: Res<Assets<Image>>,
+    mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+) {
+    for e in q_pending.iter() {
+        // `SelectableQuery` skips entities that are still being placed
+        if let Ok((e, _, spr, h_img, shape, circle, polygon)) = q.get(e) {
+            if let Some(pick) = PickShape::new(spr, h_img, shape, circle, polygon, &imgs) {
+                select_entity(&mut cmd, &mut sels, &mut active, e, pick.size());
             }
+            cmd.entity(e).remove::<PendingSelect>();
+        }
+    }
+}
+
+/// T: select everything of the same blueprint type as the active selection
+pub fn keyboard_select_same_kind(
+    mut cmd: Commands,
+    kbd: Res<Input<KeyCode>>,
+    q: SelectableQuery,
+    q_kind: Query<&BlueprintKind>,
+    imgs: Res<Assets<Image>>,
+    mut sels: ResMut<Selections>,
+    mut active: ResMut<ActiveSelection>,
+) {
+    if !kbd.just_pressed(KeyCode::T) {
+        return;
+    }
+    let kind = if let Some(kind) = active.0.and_then(|e| q_kind.get(e).ok()) {
+        *kind
+    } else {
+        return;
+    };
+    let prev_active = active.0;
+    for (e, _, spr, h_img, shape, circle, polygon) in q.iter() {
+        if q_kind.get(e).ok() != Some(&kind) {
+            continue;
+        }
+        if let Some(pick) = PickShape::new(spr, h_img, shape, circle, polygon, &imgs) {
+            select_entity(&mut cmd, &mut sels, &mut active, e, pick.size());
+        }
+    }
+    active.0 = prev_active;
+}
+
+/// P: attach all selected entities to the active one
+/// Shift+P: detach all selected entities from their parents
+///
+/// Entities keep their position in the world either way.
+pub fn keyboard_parent_selected(
+    mut cmd: Commands,
+    kbd: Res<Input<KeyCode>>,
+    sels: Res<Selections>,
+    active: Res<ActiveSelection>,
+    q_parent: Query<&Parent>,
+    q_gxf: Query<&GlobalTransform>,
+) {
+    if !kbd.just_pressed(KeyCode::P) {
+        return;
+    }
+    let shift = kbd.pressed(KeyCode::LShift) || kbd.pressed(KeyCode::RShift);
+
+    if shift {
+        for e in sels.0.keys() {
+            if q_parent.get(*e).is_err() {
+                continue;
+            }
+            if let Ok(gxf) = q_gxf.get(*e) {
+                cmd.entity(*e)
+                    .remove::<Parent>()
+                    .insert(Transform::from(*gxf));
+            }
+        }
+        return;
+    }
+
+    let parent = match active.0 {
+        Some(e) if sels.0.contains_key(&e) => e,
+        _ => return,
+    };
+    let parent_inv = if let Ok(gxf) = q_gxf.get(parent) {
+        gxf.compute_matrix().inverse()
+    } else {
+        return;
+    };
+    // the selected entities that the new parent is itself a descendant of
+    // cannot become its children
+    let mut ancestors = Vec::new();
+    let mut cur = parent;
+    while let Ok(p) = q_parent.get(cur) {
+        ancestors.push(p.0);
+        cur = p.0;
+    }
+    for e in sels.0.keys() {
+        if *e == parent || ancestors.contains(e) {
+            continue;
+        }
+        if let Ok(gxf) = q_gxf.get(*e) {
+            let xf = Transform::from_matrix(parent_inv * gxf.compute_matrix());
+            cmd.entity(*e).insert(xf);
+            cmd.entity(parent).push_children(&[*e]);
         }
     }
 }
@@ -167,56 +507,19 @@ pub fn selection_track_target(
 }
 
 pub fn selection_track_collider(
-    mut cmd: Commands,
-    mut sels: ResMut<Selections>,
-    mut q_sel: Query<(Entity, &mut Sprite, &Selection)>,
-    q_tgt: Query<&CollisionShape>,
-) {
-    for (e, mut spr, sel) in q_sel.iter_mut() {
-        if let Ok(shape) = q_tgt.get(sel.0) {
-            match shape {
-                CollisionShape::Cuboid { half_extends, .. } => {
-                    spr.custom_size = Some(half_extends.truncate() * 2.0);
-                }
-                _ => {
-                    cmd.entity(e).despawn_recursive();
-                    sels.0.remove(&sel.0);
-                }
-            }
-        }
-    }
-}
-
-pub(super) fn keyboard_duplicate_collider(
-    mut cmd: Commands,
-    mut sels: ResMut<Selections>,
-    q_sel: Query<(Entity, &Selection)>,
-    q_src: Query<(&Transform, &EditableCollider, &ColliderKind)>,
-    kbd: Res<Input<KeyCode>>,
-    mut toolstate: ResMut<State<ToolState>>,
+    mut q_sel: Query<(&mut Sprite, &Selection)>,
+    q_tgt: Query<(
+        Option<&CollisionShape>,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    ), Or<(With<CollisionShape>, With<EditableCircle>, With<EditablePolygon>)>>,
+    imgs: Res<Assets<Image>>,
 ) {
-    if kbd.just_pressed(KeyCode::D) {
-        for (e, sel) in q_sel.iter() {
-            if let Ok((xf, edit, kind)) = q_src.get(sel.0) {
-                // spawn new collider copying transform and dimensions
-                let mut new = cmd.spawn();
-                new
-                    .insert(GlobalTransform::default())
-                    .insert(*xf)
-                    .insert(edit.clone())
-                    .insert(*kind)
-                    .insert(NewlySpawned);
-                kind.insert(&mut new);
-                let new = new.id();
-                // add selection for it
-                let newsel = cmd.spawn_bundle(SelectionBundle::new(new, edit.half_extends * 2.0)).id();
-                sels.0.insert(new, newsel);
-                // spawning state
-                toolstate.set(ToolState::Spawning).ok();
+    for (mut spr, sel) in q_sel.iter_mut() {
+        if let Ok((shape, circle, polygon)) = q_tgt.get(sel.0) {
+            if let Some(pick) = PickShape::new(None, None, shape, circle, polygon, &imgs) {
+                spr.custom_size = Some(pick.size());
             }
-            // remove all existing selections
-            cmd.entity(e).despawn_recursive();
-            sels.0.remove(&sel.0);
         }
     }
 }
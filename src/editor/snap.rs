@@ -0,0 +1,120 @@
+//! Grid and angle snapping for the editor tools
+//!
+//! Snapping is on by default; hold Alt to move/rotate/resize freely.
+//! `[` and `]` change the grid size, `G` toggles the grid overlay.
+
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+
+use crate::util::MainCamera;
+
+const GRID_SIZES: [f32; 7] = [4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0];
+/// Don't draw more lines than this in each direction; use a coarser grid instead
+const GRID_MAX_LINES: f32 = 100.0;
+const GRID_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const GRID_AXIS_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.4);
+
+pub struct SnapSettings {
+    pub enabled: bool,
+    /// Index into `GRID_SIZES`
+    grid_idx: usize,
+    /// Angle increment for the Rotate tool, in degrees
+    pub angle: f32,
+    pub show_grid: bool,
+}
+
+impl Default for SnapSettings {
+    fn default() -> Self {
+        SnapSettings {
+            enabled: true,
+            grid_idx: 3,
+            angle: 15.0,
+            show_grid: true,
+        }
+    }
+}
+
+impl SnapSettings {
+    pub fn grid(&self) -> f32 {
+        GRID_SIZES[self.grid_idx]
+    }
+
+    /// Is snapping on right now, taking the modifier key into account?
+    pub fn active(&self, kbd: &Input<KeyCode>) -> bool {
+        let alt = kbd.pressed(KeyCode::LAlt) || kbd.pressed(KeyCode::RAlt);
+        self.enabled != alt
+    }
+
+    pub fn snap_pos(&self, pos: Vec2) -> Vec2 {
+        (pos / self.grid()).round() * self.grid()
+    }
+
+    /// Snap half extents, so that the full size is a multiple of the grid
+    pub fn snap_extents(&self, half_extents: Vec2) -> Vec2 {
+        let full = (half_extents * 2.0 / self.grid()).round() * self.grid();
+        full.max(Vec2::splat(self.grid())) / 2.0
+    }
+
+    /// Snap an angle in radians
+    pub fn snap_angle(&self, angle: f32) -> f32 {
+        let step = self.angle.to_radians();
+        (angle / step).round() * step
+    }
+}
+
+pub(super) fn snap_hotkeys(
+    kbd: Res<Input<KeyCode>>,
+    mut snap: ResMut<SnapSettings>,
+) {
+    if kbd.just_pressed(KeyCode::LBracket) && snap.grid_idx > 0 {
+        snap.grid_idx -= 1;
+        info!("Editor grid size: {}", snap.grid());
+    }
+    if kbd.just_pressed(KeyCode::RBracket) && snap.grid_idx < GRID_SIZES.len() - 1 {
+        snap.grid_idx += 1;
+        info!("Editor grid size: {}", snap.grid());
+    }
+    if kbd.just_pressed(KeyCode::G) {
+        snap.show_grid = !snap.show_grid;
+    }
+}
+
+pub(super) fn draw_grid(
+    snap: Res<SnapSettings>,
+    windows: Res<Windows>,
+    mut lines: ResMut<DebugLines>,
+    q_cam: Query<&Transform, With<MainCamera>>,
+) {
+    if !snap.show_grid {
+        return;
+    }
+    let window = if let Some(window) = windows.get_primary() {
+        window
+    } else {
+        return;
+    };
+    let cam = q_cam.single();
+    let half_view = Vec2::new(window.width(), window.height()) / 2.0 * cam.scale.truncate();
+    let min = cam.translation.truncate() - half_view;
+    let max = cam.translation.truncate() + half_view;
+
+    // when zoomed out, draw every n-th line
+    let mut step = snap.grid();
+    while (max - min).max_element() / step > GRID_MAX_LINES {
+        step *= 2.0;
+    }
+
+    let z = 999.0;
+    let mut x = (min.x / step).floor() * step;
+    while x <= max.x {
+        let color = if x == 0.0 { GRID_AXIS_COLOR } else { GRID_COLOR };
+        lines.line_colored(Vec3::new(x, min.y, z), Vec3::new(x, max.y, z), 0.0, color);
+        x += step;
+    }
+    let mut y = (min.y / step).floor() * step;
+    while y <= max.y {
+        let color = if y == 0.0 { GRID_AXIS_COLOR } else { GRID_COLOR };
+        lines.line_colored(Vec3::new(min.x, y, z), Vec3::new(max.x, y, z), 0.0, color);
+        y += step;
+    }
+}
@@ -0,0 +1,86 @@
+//! Minimal keyboard text entry for the editor ui
+//!
+//! Bevy ui has no text fields, so whoever needs text from the user
+//! calls `TextInput::begin` and waits for a `TextInputSubmit` event.
+//! While typing, all other keyboard input is swallowed, so that
+//! editor hotkeys don't fire.
+
+use bevy::prelude::*;
+
+/// What the text being typed is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextInputTarget {
+    SaveSceneAs,
+    SavePrefab,
+    InspectorField,
+}
+
+#[derive(Default)]
+pub struct TextInput {
+    target: Option<TextInputTarget>,
+    pub buffer: String,
+}
+
+impl TextInput {
+    pub fn begin(&mut self, target: TextInputTarget, initial: impl Into<String>) {
+        self.target = Some(target);
+        self.buffer = initial.into();
+    }
+
+    pub fn cancel(&mut self) {
+        self.target = None;
+        self.buffer.clear();
+    }
+
+    /// The target, if the user is currently typing for it
+    pub fn active(&self) -> Option<TextInputTarget> {
+        self.target
+    }
+}
+
+/// Sent when the user presses Enter
+pub struct TextInputSubmit {
+    pub target: TextInputTarget,
+    pub text: String,
+}
+
+/// Must run after input handling and before anything that reads the keyboard
+pub fn text_input_keyboard(
+    mut input: ResMut<TextInput>,
+    mut kbd: ResMut<Input<KeyCode>>,
+    mut evr_char: EventReader<ReceivedCharacter>,
+    mut evw_submit: EventWriter<TextInputSubmit>,
+) {
+    let target = if let Some(target) = input.target {
+        target
+    } else {
+        // don't let stale characters leak into the next prompt
+        evr_char.iter().for_each(drop);
+        return;
+    };
+
+    for ev in evr_char.iter() {
+        if !ev.char.is_control() {
+            input.buffer.push(ev.char);
+        }
+    }
+    if kbd.just_pressed(KeyCode::Back) {
+        input.buffer.pop();
+    }
+    if kbd.just_pressed(KeyCode::Return) {
+        evw_submit.send(TextInputSubmit {
+            target,
+            text: std::mem::take(&mut input.buffer),
+        });
+        input.target = None;
+    }
+    if kbd.just_pressed(KeyCode::Escape) {
+        input.cancel();
+    }
+
+    kbd.clear();
+}
+
+pub fn cancel_text_input(mut input: ResMut<TextInput>) {
+    input.cancel();
+}
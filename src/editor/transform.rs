@@ -1,55 +1,101 @@
-use bevy::{prelude::*, math::Vec3Swizzles, input::mouse::{MouseMotion, MouseWheel, MouseScrollUnit}};
+use bevy::{prelude::*, math::Vec3Swizzles};
 
-use crate::util::{WorldCursor, WorldCursorPrev, MainCamera};
+use bevy::utils::HashMap;
 
-use super::{select::Selection, UsingTool, NewlySpawned, ToolState};
+use crate::util::{WorldCursor, WorldCursorPrev};
 
-pub fn editor_camera(
-    mut q_cam: Query<&mut Transform, With<MainCamera>>,
-    mut evr_motion: EventReader<MouseMotion>,
-    btn: Res<Input<MouseButton>>,
-) {
-    if btn.pressed(MouseButton::Right) {
-        let mut xf = q_cam.single_mut();
-        let mut delta = Vec2::ZERO;
-        for ev in evr_motion.iter() {
-            delta += ev.delta;
+use super::{select::{ActiveSelection, Selections}, snap::SnapSettings, UsingTool, NewlySpawned, ToolState};
+
+/// Is any ancestor of the entity also selected?
+///
+/// Such entities are moved along with their parent, so the tools leave them alone.
+fn ancestor_selected(e: Entity, sels: &Selections, q_parent: &Query<&Parent>) -> bool {
+    let mut cur = e;
+    while let Ok(parent) = q_parent.get(cur) {
+        if sels.0.contains_key(&parent.0) {
+            return true;
         }
-        delta *= xf.scale.truncate();
-        xf.translation.x -= delta.x;
-        xf.translation.y += delta.y;
+        cur = parent.0;
     }
+    false
 }
 
-pub fn editor_camera_zoom(
-    mut q_cam: Query<&mut Transform, With<MainCamera>>,
-    mut evr_motion: EventReader<MouseWheel>,
-) {
-    for ev in evr_motion.iter() {
-        let mut xf = q_cam.single_mut();
-        if ev.y > 0.0 {
-            xf.scale *= 1.25;
-        } else if ev.y < 0.0 {
-            xf.scale *= 0.75;
+/// Where the cursor and the selected entities were when the mouse button was pressed
+///
+/// Move and Rotate work from this, rather than from the per-frame cursor delta,
+/// so that snapping doesn't get stuck.
+#[derive(Default)]
+pub struct ToolDragStart {
+    crs: Vec2,
+    entities: HashMap<Entity, (Transform, GlobalTransform)>,
+    /// Snapped when moving; the others keep their offsets from it
+    anchor: Option<Entity>,
+}
+
+impl ToolDragStart {
+    fn begin(
+        &mut self,
+        crs: Vec2,
+        sels: &Selections,
+        q_parent: &Query<&Parent>,
+        q_gxf: &Query<&GlobalTransform>,
+        q_tgt: &Query<&mut Transform, Without<NewlySpawned>>,
+        active: Option<Entity>,
+    ) {
+        self.crs = crs;
+        self.entities.clear();
+        for e in sels.0.keys() {
+            if ancestor_selected(*e, sels, q_parent) {
+                continue;
+            }
+            if let (Ok(xf), Ok(gxf)) = (q_tgt.get(*e), q_gxf.get(*e)) {
+                self.entities.insert(*e, (*xf, *gxf));
+            }
         }
+        self.anchor = match active {
+            Some(active) if self.entities.contains_key(&active) => Some(active),
+            _ => self.entities.keys().copied().min_by_key(|e| e.id()),
+        };
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn mouse_move_selections(
     crs: Res<WorldCursor>,
-    crs_old: Res<WorldCursorPrev>,
-    q_sel: Query<&Selection>,
+    kbd: Res<Input<KeyCode>>,
+    snap: Res<SnapSettings>,
+    mut start: ResMut<ToolDragStart>,
+    sels: Res<Selections>,
+    active: Res<ActiveSelection>,
+    q_parent: Query<&Parent>,
+    q_gxf: Query<&GlobalTransform>,
     mut q_tgt: Query<&mut Transform, Without<NewlySpawned>>,
     btn: Res<Input<MouseButton>>,
 ) {
+    if btn.just_pressed(MouseButton::Left) {
+        start.begin(crs.0, &sels, &q_parent, &q_gxf, &q_tgt, active.0);
+    }
     if btn.pressed(MouseButton::Left) {
-        // TODO: does not work in children in hierarchy;
-        // needs reverse transform propagation
-        let delta = crs.0 - crs_old.0;
-        for sel in q_sel.iter() {
-            if let Ok(mut xf) = q_tgt.get_mut(sel.0) {
-                xf.translation.x += delta.x;
-                xf.translation.y += delta.y;
+        let mut delta = crs.0 - start.crs;
+        // snap one entity, and move all of them by the same amount
+        let anchor = start.anchor.and_then(|anchor| start.entities.get(&anchor));
+        if let Some((_, anchor_gxf)) = anchor.filter(|_| snap.active(&kbd)) {
+            let anchor_pos = anchor_gxf.translation.truncate();
+            delta = snap.snap_pos(anchor_pos + delta) - anchor_pos;
+        }
+        for (e, (xf0, _)) in start.entities.iter() {
+            // convert the world-space delta into the space of the parent
+            let delta = delta.extend(0.0);
+            let delta = if let Some(parent_gxf) = q_parent.get(*e).ok()
+                .and_then(|parent| q_gxf.get(parent.0).ok())
+            {
+                parent_gxf.compute_matrix().inverse().transform_vector3(delta)
+            } else {
+                delta
+            };
+            if let Ok(mut xf) = q_tgt.get_mut(*e) {
+                xf.translation.x = xf0.translation.x + delta.x;
+                xf.translation.y = xf0.translation.y + delta.y;
             }
         }
     }
@@ -63,34 +109,62 @@ pub(super) fn mouse_move_newlyspawned(
     mut commands: Commands,
     mut toolstate: ResMut<State<ToolState>>,
     tool: Res<UsingTool>,
+    kbd: Res<Input<KeyCode>>,
+    snap: Res<SnapSettings>,
 ) {
     for (e, mut xf) in q_tgt.iter_mut() {
         let delta = crs.0 - crs_old.0;
         xf.translation.x += delta.x;
         xf.translation.y += delta.y;
         if btn.just_pressed(MouseButton::Left) {
+            if snap.active(&kbd) {
+                let pos = snap.snap_pos(xf.translation.truncate());
+                xf.translation.x = pos.x;
+                xf.translation.y = pos.y;
+            }
             toolstate.push(ToolState::Using(*tool)).ok();
             commands.entity(e).remove::<NewlySpawned>();
         }
     }
 }
 
+/// Angle of a 2D rotation around the Z axis
+pub(super) fn z_angle(rot: Quat) -> f32 {
+    let (axis, angle) = rot.to_axis_angle();
+    angle * axis.z.signum()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn mouse_rotate_selections(
     crs: Res<WorldCursor>,
-    crs_old: Res<WorldCursorPrev>,
-    q_sel: Query<&Selection>,
+    kbd: Res<Input<KeyCode>>,
+    snap: Res<SnapSettings>,
+    mut start: ResMut<ToolDragStart>,
+    sels: Res<Selections>,
+    active: Res<ActiveSelection>,
+    q_parent: Query<&Parent>,
+    q_gxf: Query<&GlobalTransform>,
     mut q_tgt: Query<&mut Transform, Without<NewlySpawned>>,
     btn: Res<Input<MouseButton>>,
 ) {
+    if btn.just_pressed(MouseButton::Left) {
+        start.begin(crs.0, &sels, &q_parent, &q_gxf, &q_tgt, active.0);
+    }
     if btn.pressed(MouseButton::Left) {
-        // TODO: does not work in children in hierarchy;
-        // needs reverse transform propagation
-        for sel in q_sel.iter() {
-            if let Ok(mut xf) = q_tgt.get_mut(sel.0) {
-                let ray0 = crs_old.0 - xf.translation.xy();
-                let ray1 = crs.0 - xf.translation.xy();
-                let angle = ray0.angle_between(ray1);
-                xf.rotate(Quat::from_rotation_z(angle));
+        for (e, (xf0, gxf0)) in start.entities.iter() {
+            // rotate around where the entity actually is in the world
+            let center = gxf0.translation.xy();
+            let ray0 = start.crs - center;
+            let ray1 = crs.0 - center;
+            if ray0 == Vec2::ZERO || ray1 == Vec2::ZERO {
+                continue;
+            }
+            let mut angle = z_angle(xf0.rotation) + ray0.angle_between(ray1);
+            if snap.active(&kbd) {
+                angle = snap.snap_angle(angle);
+            }
+            if let Ok(mut xf) = q_tgt.get_mut(*e) {
+                xf.rotation = Quat::from_rotation_z(angle);
             }
         }
     }
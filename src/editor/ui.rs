@@ -1,11 +1,10 @@
-use std::marker::PhantomData;
-
 use bevy::prelude::*;
 use enum_iterator::IntoEnumIterator;
 
-use crate::{ui::{UiAssets, UiConfig}, game::blueprints::Blueprint, util::WorldCursor, AppState};
+use crate::{ui::{UiAssets, UiConfig}, game::blueprints::BlueprintRegistry, util::WorldCursor, AppState};
 
 use super::{UsingTool, EditorHideCleanup, NewlySpawned, ToolState, Editable};
+use super::prefabs::PrefabListParent;
 
 #[derive(Component)]
 struct EditorBtn;
@@ -16,8 +15,9 @@ pub struct SpawnBtnParent(Option<Entity>);
 #[derive(Component, Clone, Copy)]
 pub(super) struct ToolBtn(UsingTool);
 
+/// Spawns the blueprint with this `EDITOR_ID`
 #[derive(Component, Clone, Copy)]
-pub(super) struct SpawnBtn<T: Blueprint>(PhantomData<T>);
+pub(super) struct SpawnBtn(&'static str);
 
 impl ToolBtn {
     fn label(&self) -> &'static str {
@@ -26,6 +26,7 @@ impl ToolBtn {
             UsingTool::Move => "Move",
             UsingTool::Rotate => "Rotate",
             UsingTool::EditCollider => "Resize Collider",
+            UsingTool::Link => "Link Triggers",
         }
     }
 }
@@ -41,20 +42,24 @@ pub(super) fn tool_btn_handler(
     }
 }
 
-pub(super) fn spawn_btn_handler<T: Blueprint>(
-    In(clicked): In<Option<SpawnBtn<T>>>,
+pub(super) fn spawn_btn_handler(
+    In(clicked): In<Option<SpawnBtn>>,
     mut commands: Commands,
     mut toolstate: ResMut<State<ToolState>>,
     mut btn: ResMut<Input<MouseButton>>,
     crs: Res<WorldCursor>,
+    registry: Res<BlueprintRegistry>,
 ) {
-    if clicked.is_none() {
+    let blueprint = if let Some(bp) = clicked.and_then(|clicked| registry.get(clicked.0)) {
+        bp
+    } else {
         return;
-    }
+    };
     btn.clear_just_pressed(MouseButton::Left);
     toolstate.set(ToolState::Spawning).ok();
-    commands.spawn_bundle(T::BlueprintBundle::default())
-        .insert(Transform::from_translation(crs.0.extend(T::DEFAULT_Z)))
+    let e = (blueprint.spawn)(&mut commands);
+    commands.entity(e)
+        .insert(Transform::from_translation(crs.0.extend(blueprint.z)))
         .insert(GlobalTransform::default())
         .insert(crate::scene_exporter::SaveSceneMarker)
         .insert(Editable)
@@ -91,11 +96,13 @@ pub(super) fn tool_btn_visual(
     }
 }
 
-pub(super) fn add_spawn_button<T: Blueprint>(
+/// One button per registered blueprint type, in the row made by `spawn_ui`
+pub(super) fn add_spawn_buttons(
     mut cmd: Commands,
     uicfg: Res<UiConfig>,
     uiassets: Res<UiAssets>,
     btnrow: Res<SpawnBtnParent>,
+    registry: Res<BlueprintRegistry>,
 ) {
     let textstyle_btn = TextStyle {
         color: Color::BLACK,
@@ -103,23 +110,24 @@ pub(super) fn add_spawn_button<T: Blueprint>(
         font: uiassets.font_menu_regular.clone(),
     };
 
-    let spbtn = SpawnBtn::<T>(PhantomData);
-    let btntext = cmd.spawn_bundle(TextBundle {
-        text: Text::with_section(
-            T::EDITOR_ID,
-            textstyle_btn.clone(),
-            Default::default()
-        ),
-        ..Default::default()
-    }).id();
+    for blueprint in registry.blueprints.iter() {
+        let btntext = cmd.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                blueprint.id,
+                textstyle_btn.clone(),
+                Default::default()
+            ),
+            ..Default::default()
+        }).id();
 
-    let btn = cmd.spawn_bundle(ButtonBundle {
-        color: UiColor(Color::rgb(0.75, 0.75, 0.75)),
-        style: uicfg.btn_style.clone(),
-        ..Default::default()
-    }).insert(EditorBtn).insert(spbtn).id();
-    cmd.entity(btn).push_children(&[btntext]);
-    cmd.entity(btnrow.0.unwrap()).push_children(&[btn]);
+        let btn = cmd.spawn_bundle(ButtonBundle {
+            color: UiColor(Color::rgb(0.75, 0.75, 0.75)),
+            style: uicfg.btn_style.clone(),
+            ..Default::default()
+        }).insert(EditorBtn).insert(SpawnBtn(blueprint.id)).id();
+        cmd.entity(btn).push_children(&[btntext]);
+        cmd.entity(btnrow.0.unwrap()).push_children(&[btn]);
+    }
 }
 
 pub(super) fn spawn_ui(
@@ -127,6 +135,7 @@ pub(super) fn spawn_ui(
     uiassets: Res<UiAssets>,
     uicfg: Res<UiConfig>,
     mut r_btnrow: ResMut<SpawnBtnParent>,
+    mut r_prefabrow: ResMut<PrefabListParent>,
 ) {
     let textstyle_btn = TextStyle {
         color: Color::BLACK,
@@ -263,7 +272,39 @@ pub(super) fn spawn_ui(
         ..Default::default()
     }).id();
 
-    cmd.entity(top2).push_children(&[heading, btnrow]);
+    let prefab_heading_text = cmd.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "PREFABS:",
+            uicfg.heading_style_text.clone(),
+            Default::default()
+        ),
+        style: Style {
+            align_self: AlignSelf::Center,
+            margin: Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    let prefabrow = cmd.spawn_bundle(NodeBundle {
+        color: UiColor(Color::NONE),
+        style: Style {
+            size: Size::new(Val::Auto, Val::Auto),
+            padding: Rect::all(Val::Px(4.0)),
+            flex_grow: 0.0,
+            flex_shrink: 1.0,
+            align_self: AlignSelf::Center,
+            flex_direction: FlexDirection::Row,
+            flex_wrap: FlexWrap::Wrap,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }).id();
+
+    cmd.entity(top2).push_children(&[heading, btnrow, prefab_heading_text, prefabrow]);
 
     r_btnrow.0 = Some(btnrow);
+    r_prefabrow.0 = Some(prefabrow);
 }
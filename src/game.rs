@@ -8,6 +8,7 @@ mod hints;
 mod main_camera;
 mod phys_layers;
 pub(crate) mod player;
+pub mod savegame;
 pub(crate) mod shooting;
 mod timer;
 
@@ -41,6 +42,7 @@ pub use dev::DevPlaygroundPlugin;
 
 pub mod blueprints;
 pub mod collider;
+pub mod tiled;
 
 /// This plugin should add all common game systems used in all levels
 pub struct GamePlugin<S: BevyState> {
@@ -53,6 +55,22 @@ pub struct GamePlugin<S: BevyState> {
 #[derive(Component)]
 pub struct GameCleanup;
 
+/// Present once the scene of the scenario has been spawned
+pub struct ScenarioSceneSpawned;
+
+/// Spawn the scene of a scenario right away, for use in an exclusive `on_enter` system
+///
+/// `SceneSpawner::spawn_dynamic` would spawn it some time later, with no way
+/// to tell when; things like placing the player need to know it is there.
+pub fn spawn_scenario_scene(world: &mut World, scene: &Handle<DynamicScene>) {
+    world.resource_scope(|world, mut scene_spawner: Mut<SceneSpawner>| {
+        if let Err(e) = scene_spawner.spawn_dynamic_sync(world, scene) {
+            error!("Could not spawn the scenario's scene: {:?}", e);
+        }
+    });
+    world.insert_resource(ScenarioSceneSpawned);
+}
+
 impl<S: BevyState> Plugin for GamePlugin<S> {
     fn build(&self, app: &mut App) {
         app.insert_resource(AudioChannelPool::default());
@@ -71,11 +89,15 @@ impl<S: BevyState> Plugin for GamePlugin<S> {
                 .with_system(setup_crosshair)
                 .with_system(animations_init)
                 .with_system(init_player)
+                .with_system(init_player_start)
                 .with_system(init_hints)
+                .with_system(savegame::init_used_pickups)
+                .with_system(savegame::init_destroyed_barrels)
                 .with_system(set_cursor_visibility::<false>),
         );
         let _x = app.add_system_set(
             SystemSet::on_update(self.state.clone())
+                .with_system(savegame::save_game_hotkeys)
                 .with_system(exit_game_on_esc)
                 // .with_system(debug_enemy_spawn)
                 // .with_system(enemy_debug_lines)
@@ -90,6 +112,7 @@ impl<S: BevyState> Plugin for GamePlugin<S> {
                 )
                 .with_system(refresh_camera_position_system.after("recalculate_camera"))
                 .with_system(print_player_position)
+                .with_system(place_player_at_start)
                 // enemies
                 //.with_system(enemy_controller.label("enemy_controller"))
                 .with_system(spawn_zones)
@@ -145,6 +168,12 @@ impl<S: BevyState> Plugin for GamePlugin<S> {
                 .with_system(medkit_interaction.after("trigger_interaction"))
                 .with_system(ammo_box_interaction.after("trigger_interaction"))
                 .with_system(process_interactable_despawn)
+                // save games
+                .with_system(savegame::record_used_pickups)
+                .with_system(savegame::restore_used_pickups)
+                .with_system(savegame::record_destroyed_barrels)
+                .with_system(savegame::restore_destroyed_barrels)
+                .with_system(savegame::apply_pending_restore)
                 // spatial sound
                 .with_system(spatial_audio.after("spatial_audio_added"))
                 .with_system(spatial_audio_changed.after("spatial_audio_added"))
@@ -156,6 +185,10 @@ impl<S: BevyState> Plugin for GamePlugin<S> {
                 .with_system(despawn_with_recursive::<GameCleanup>)
                 .with_system(despawn_with::<MainCamera>)
                 .with_system(remove_resource::<GameTimer>)
+                .with_system(remove_resource::<savegame::UsedPickups>)
+                .with_system(remove_resource::<savegame::DestroyedBarrels>)
+                .with_system(remove_resource::<PlayerStartPending>)
+                .with_system(remove_resource::<ScenarioSceneSpawned>)
                 .with_system(set_cursor_visibility::<true>),
         );
         app.add_system_set(
@@ -1,33 +1,40 @@
 //! # HOW TO ADD A NEW BLUEPRINT TYPE
 //!
 //! - have a unique marker component type
-//! - `impl Blueprint for MyMarker {}`
-//!   - fill it out with the info for the editor
-//! - register it in `BlueprintsPlugin`
-//! - insert it in `add_blueprint_meta`
 //! - create new init function
 //!   (you can copypaste `init_bp_collider` as a template)
 //!   - use your new marker, in the `BlueprintQuery` param
 //!   - in the body, insert whatever components you want
 //!   - be sure to preserve the transform
+//! - `impl Blueprint for MyMarker {}`
+//!   - fill it out with the info for the editor
+//!   - add your init function in `register_systems`
+//! - call `app.add_blueprint::<MyMarker>()` in `BlueprintsPlugin`
+//!   - any other reflected components your blueprint stores in scenes
+//!     need `app.add_exportable_type::<T>()`
 //!
 
 #![allow(unused_imports)]
 
+use std::any::TypeId;
+
 use bevy::ecs::system::EntityCommands;
 use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy::reflect::{GetTypeRegistration, TypeRegistry};
 use bevy::utils::HashSet;
 use heron::*;
 
 use crate::editor::collider::ColliderEditorVisColor;
 use crate::editor::collider::EditableCollider;
+use crate::editor::collider::{EditableCircle, EditablePolygon};
 use crate::editor::Editable;
 use crate::editor::NewlySpawned;
+use crate::scene_exporter::stable_id::{resolve_entity_refs, EntityRef, EntityRefs, EntityRefsTypes, StableId};
 use crate::FuckStages;
 
 use super::collider;
-use super::collider::ColliderKind;
+use super::collider::{ColliderGeometry, ColliderKind};
 use super::GameAssets;
 use super::GameCleanup;
 
@@ -46,36 +53,41 @@ pub struct BlueprintsPlugin;
 
 impl Plugin for BlueprintsPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<ExportableTypes>();
+        app.init_resource::<BlueprintRegistry>();
         // registration: add our own types that should be exported to scenes:
-        app.register_type::<Medkit>();
-        app.register_type::<AmmoBox>();
-        app.register_type::<MultiUse>();
-        app.register_type::<EditableCollider>();
-        app.register_type::<collider::Wall>();
-        app.register_type::<collider::HurtZone>();
-        app.register_type::<collider::WinZone>();
-        app.register_type::<collider::SpawnZone>();
-        app.add_startup_system(add_blueprint_meta);
-        //
-        app.add_system_set_to_stage(
-            FuckStages::Post,
-            SystemSet::new()
-                .with_system(init_bp_medkit)
-                .with_system(init_bp_ammo_box)
-                .with_system(init_bp_collider::<collider::Wall>)
-                .with_system(init_bp_collider::<collider::HurtZone>)
-                .with_system(init_bp_collider::<collider::WinZone>)
-                .with_system(init_bp_collider::<collider::SpawnZone>)
-                .with_system(collider_apply_sync::<collider::Wall>)
-                .with_system(collider_apply_sync::<collider::HurtZone>)
-                .with_system(collider_apply_sync::<collider::WinZone>)
-                .with_system(collider_apply_sync::<collider::SpawnZone>),
-        );
+        app.add_exportable_type::<Transform>();
+        // hierarchies; `Children` is rebuilt by bevy from this
+        app.add_exportable_type::<Parent>();
+        app.add_exportable_type::<MultiUse>();
+        app.add_exportable_type::<EditableCollider>();
+        app.add_exportable_type::<EditableCircle>();
+        app.add_exportable_type::<EditablePolygon>();
+        app.add_exportable_type::<crate::scene_exporter::prefab::PrefabInstance>();
+        app.add_exportable_type::<crate::editor::camera::CameraBookmark>();
+        app.add_exportable_type::<StableId>();
+        app.register_type::<EntityRef>();
+        app.add_entity_refs::<TriggerTargets>();
+        // enums stored in blueprints
+        app.register_type::<door::DoorRotationSide>();
+        app.register_type::<door::DoorLockStatus>();
+        app.register_type::<door::DoorOpenStatus>();
+        // blueprints:
+        app.add_blueprint::<collider::Wall>();
+        app.add_blueprint::<collider::HurtZone>();
+        app.add_blueprint::<collider::SpawnZone>();
+        app.add_blueprint::<collider::WinZone>();
+        app.add_blueprint::<collider::Sensor>();
+        app.add_blueprint::<Medkit>();
+        app.add_blueprint::<AmmoBox>();
+        app.add_blueprint::<PlayerStart>();
+        app.add_blueprint::<door::Door>();
+        app.add_blueprint::<barrel::ExplosiveBarrel>();
     }
 }
 
 /// impl this Trait for a marker type to enable using it with the editor
-pub trait Blueprint: Component + Reflect + Default + Clone {
+pub trait Blueprint: Component + Reflect + GetTypeRegistration + Default + Clone {
     /// Text for the spawn button in the editor ui
     const EDITOR_ID: &'static str;
     /// Z coordinate to spawn at
@@ -84,26 +96,108 @@ pub trait Blueprint: Component + Reflect + Default + Clone {
     /// The bundle to use when spawning from the editor
     /// The editor will spawn the new entity with a default instance of this bundle
     type BlueprintBundle: Bundle + Default;
+
+    /// Add the systems that initialize newly spawned blueprint entities
+    fn register_systems(sset: SystemSet) -> SystemSet;
 }
 
 /// List of types that may be serialized by the scene exporter
+#[derive(Default)]
 pub struct ExportableTypes {
-    pub names: HashSet<&'static str>,
+    pub names: HashSet<String>,
+}
+
+/// Every blueprint type, in the order they were added
+///
+/// The editor makes its spawn buttons and layers from this.
+#[derive(Default)]
+pub struct BlueprintRegistry {
+    pub blueprints: Vec<RegisteredBlueprint>,
+}
+
+pub struct RegisteredBlueprint {
+    /// `Blueprint::EDITOR_ID`
+    pub id: &'static str,
+    /// `Blueprint::DEFAULT_Z`
+    pub z: f32,
+    /// Spawn a default instance of the blueprint bundle
+    pub spawn: fn(&mut Commands) -> Entity,
+}
+
+impl BlueprintRegistry {
+    pub fn get(&self, id: &str) -> Option<&RegisteredBlueprint> {
+        self.blueprints.iter().find(|bp| bp.id == id)
+    }
+}
+
+fn spawn_default_blueprint<T: Blueprint>(commands: &mut Commands) -> Entity {
+    commands.spawn_bundle(T::BlueprintBundle::default()).id()
 }
 
-fn add_blueprint_meta(mut commands: Commands) {
-    let mut names = HashSet::default();
-    // add everything that might be used in a blueprint here:
-    names.insert("Transform");
-    names.insert("Medkit");
-    names.insert("AmmoBox");
-    names.insert("MultiUse");
-    names.insert("EditableCollider");
-    names.insert("Wall");
-    names.insert("HurtZone");
-    names.insert("SpawnZone");
-    names.insert("WinZone");
-    commands.insert_resource(ExportableTypes { names });
+pub trait BlueprintAppExt {
+    /// Register a reflected component type and allow it to be exported to scenes
+    fn add_exportable_type<T: Component + Reflect + GetTypeRegistration>(&mut self) -> &mut Self;
+    /// Set up everything needed to save a blueprint type to scenes,
+    /// and list it in the `BlueprintRegistry` for the editor
+    fn add_blueprint<T: Blueprint>(&mut self) -> &mut Self;
+    /// Like `add_exportable_type`, for a component that refers to other entities;
+    /// its references are resolved after loading, and remapped when copied
+    fn add_entity_refs<T: EntityRefs + Reflect + GetTypeRegistration>(&mut self) -> &mut Self;
+}
+
+impl BlueprintAppExt for App {
+    fn add_exportable_type<T: Component + Reflect + GetTypeRegistration>(&mut self) -> &mut Self {
+        self.register_type::<T>();
+        // the scene exporter matches on the same name the registry uses
+        let name = self.world.get_resource::<TypeRegistry>().unwrap()
+            .read()
+            .get(TypeId::of::<T>())
+            .unwrap()
+            .short_name()
+            .to_owned();
+        self.world.get_resource_or_insert_with(ExportableTypes::default)
+            .names
+            .insert(name);
+        self
+    }
+
+    fn add_blueprint<T: Blueprint>(&mut self) -> &mut Self {
+        self.add_exportable_type::<T>();
+        self.add_system_set_to_stage(
+            FuckStages::Post,
+            T::register_systems(SystemSet::new()),
+        );
+        // in the next frame, so we don't race with init systems that despawn
+        self.add_system_to_stage(FuckStages::Pre, tag_blueprint_kind::<T>);
+        self.world.get_resource_or_insert_with(BlueprintRegistry::default)
+            .blueprints
+            .push(RegisteredBlueprint {
+                id: T::EDITOR_ID,
+                z: T::DEFAULT_Z,
+                spawn: spawn_default_blueprint::<T>,
+            });
+        self
+    }
+
+    fn add_entity_refs<T: EntityRefs + Reflect + GetTypeRegistration>(&mut self) -> &mut Self {
+        self.add_exportable_type::<T>();
+        self.world.get_resource_or_insert_with(EntityRefsTypes::default)
+            .register::<T>();
+        self.add_system(resolve_entity_refs::<T>.after("index_stable_ids"));
+        self
+    }
+}
+
+/// Which blueprint type an entity was spawned from (its `EDITOR_ID`)
+///
+/// Added automatically; used by the editor to select everything of the same type.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub struct BlueprintKind(pub &'static str);
+
+fn tag_blueprint_kind<T: Blueprint>(mut commands: Commands, q: Query<Entity, Added<T>>) {
+    for e in q.iter() {
+        commands.entity(e).insert(BlueprintKind(T::EDITOR_ID));
+    }
 }
 
 /// Simple generic blueprint bundle, if you only want to initialize with a transform and marker
@@ -148,16 +242,21 @@ impl Blueprint for Medkit {
     const EDITOR_ID: &'static str = "Medkit";
     const DEFAULT_Z: f32 = 1.0;
     type BlueprintBundle = MedkitBlueprintBundle;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_medkit)
+    }
 }
 
 fn init_bp_medkit(
     mut commands: Commands,
     q_bp: BlueprintQuery<Medkit>,
+    q_parent: Query<(), With<Parent>>,
     assets: Option<Res<GameAssets>>,
 ) {
     if let Some(assets) = assets {
         for (e, _medkit, xf) in q_bp.query.iter() {
-            if xf.translation == Vec3::ZERO {
+            // a child at its parent's origin was placed there on purpose
+            if xf.translation == Vec3::ZERO && q_parent.get(e).is_err() {
                 commands.entity(e)
                     .despawn();
                 continue;
@@ -226,16 +325,21 @@ impl Blueprint for AmmoBox {
     const EDITOR_ID: &'static str = "AmmoBox";
     const DEFAULT_Z: f32 = 1.0;
     type BlueprintBundle = AmmoBoxBlueprintBundle;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_ammo_box)
+    }
 }
 
 fn init_bp_ammo_box(
     mut commands: Commands,
     q_bp: BlueprintQuery<AmmoBox>,
+    q_parent: Query<(), With<Parent>>,
     assets: Option<Res<GameAssets>>,
 ) {
     if let Some(assets) = assets {
         for (e, _ammo_box, xf) in q_bp.query.iter() {
-            if xf.translation == Vec3::ZERO {
+            // a child at its parent's origin was placed there on purpose
+            if xf.translation == Vec3::ZERO && q_parent.get(e).is_err() {
                 commands.entity(e)
                     .despawn();
                 continue;
@@ -275,6 +379,165 @@ fn init_bp_ammo_box(
     }
 }
 
+// PLAYER STARTS
+
+/// Where the player spawns; if there are several, one is picked at random
+#[derive(Default, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct PlayerStart;
+
+const PLAYER_START_ICON_COLOR: Color = Color::rgba(0.25, 1.0, 1.0, 0.5);
+const PLAYER_START_ICON_SIZE: f32 = 48.0;
+
+impl Blueprint for PlayerStart {
+    const EDITOR_ID: &'static str = "PlayerStart";
+    const DEFAULT_Z: f32 = 2.0;
+    type BlueprintBundle = BasicBlueprintBundle<PlayerStart>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_player_start)
+    }
+}
+
+fn init_bp_player_start(mut commands: Commands, q_bp: BlueprintQuery<PlayerStart>) {
+    for (e, _, xf) in q_bp.query.iter() {
+        commands
+            .entity(e)
+            .insert(crate::scene_exporter::SaveSceneMarker)
+            .insert(GameCleanup)
+            .insert(Editable)
+            .insert(GlobalTransform::default())
+            .insert(*xf)
+            // only visible in the editor
+            .insert(crate::editor::collider::EditorIcon {
+                color: PLAYER_START_ICON_COLOR,
+                size: Vec2::splat(PLAYER_START_ICON_SIZE),
+            });
+    }
+}
+
+// DOORS
+
+#[derive(Bundle)]
+pub struct DoorBlueprintBundle {
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub door: door::Door,
+    /// The size of the door panel
+    pub collider: EditableCollider,
+}
+
+impl Default for DoorBlueprintBundle {
+    fn default() -> Self {
+        Self {
+            transform: Default::default(),
+            global_transform: Default::default(),
+            door: Default::default(),
+            collider: EditableCollider {
+                half_extends: Vec2::new(5.0, 50.0),
+            },
+        }
+    }
+}
+
+impl Blueprint for door::Door {
+    const EDITOR_ID: &'static str = "Door";
+    const DEFAULT_Z: f32 = 1.0;
+    type BlueprintBundle = DoorBlueprintBundle;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_door)
+            .with_system(door_apply_sync)
+    }
+}
+
+fn init_bp_door(mut commands: Commands, q_bp: BlueprintQuery<door::Door>) {
+    for (e, _, xf) in q_bp.query.iter() {
+        commands
+            .entity(e)
+            .insert(crate::scene_exporter::SaveSceneMarker)
+            .insert(GameCleanup)
+            .insert(Editable)
+            .insert(GlobalTransform::default())
+            .insert(*xf)
+            // shows the closed position in the editor
+            .insert(ColliderEditorVisColor(Color::rgba(1.0, 0.2, 1.0, 0.25)));
+    }
+}
+
+/// Regenerate the panel and sensors when a door is placed, resized or changed
+fn door_apply_sync(
+    mut commands: Commands,
+    q: Query<(Entity, &door::Door, &EditableCollider, Option<&Children>), Or<(Changed<door::Door>, Changed<EditableCollider>)>>,
+    q_part: Query<(), With<door::DoorPart>>,
+) {
+    for (e, door, edit, children) in q.iter() {
+        let parts = children.iter()
+            .flat_map(|c| c.iter().copied())
+            .filter(|c| q_part.get(*c).is_ok());
+        door::spawn_door_parts(&mut commands, e, door, edit.half_extends, parts);
+    }
+}
+
+// EXPLOSIVE BARRELS
+
+const BARREL_COLOR: Color = Color::rgb(0.8, 0.25, 0.1);
+const BARREL_RADIUS: f32 = 16.0;
+
+impl Blueprint for barrel::ExplosiveBarrel {
+    const EDITOR_ID: &'static str = "ExplosiveBarrel";
+    const DEFAULT_Z: f32 = 1.0;
+    type BlueprintBundle = BasicBlueprintBundle<barrel::ExplosiveBarrel>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_barrel)
+            .with_system(barrel_apply_sync)
+    }
+}
+
+fn init_bp_barrel(mut commands: Commands, q_bp: BlueprintQuery<barrel::ExplosiveBarrel>) {
+    for (e, _, xf) in q_bp.query.iter() {
+        commands
+            .entity(e)
+            .insert(crate::scene_exporter::SaveSceneMarker)
+            .insert(GameCleanup)
+            .insert(Editable)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(BARREL_RADIUS * 2.0)),
+                    color: BARREL_COLOR,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(GlobalTransform::default())
+            .insert(*xf)
+            .insert(RigidBody::Static)
+            .insert(
+                CollisionLayers::none()
+                    .with_group(PhysLayer::World)
+                    .with_masks(&[PhysLayer::Player, PhysLayer::Enemies, PhysLayer::Bullets]),
+            )
+            .insert(CollisionShape::Sphere {
+                radius: BARREL_RADIUS,
+            });
+    }
+}
+
+/// Reset the health and blast when a barrel is placed or changed
+fn barrel_apply_sync(
+    mut commands: Commands,
+    q: Query<(Entity, &barrel::ExplosiveBarrel), Changed<barrel::ExplosiveBarrel>>,
+) {
+    for (e, barrel) in q.iter() {
+        commands
+            .entity(e)
+            .insert(Health {
+                max: barrel.health,
+                current: barrel.health,
+            })
+            .insert(barrel::ExplosiveObjectState::NotDetonated)
+            .insert_bundle(barrel::barrel_blast(barrel));
+    }
+}
+
 // COLLIDERS
 
 #[derive(Bundle, Default)]
@@ -291,11 +554,11 @@ pub trait ColliderBehavior: Blueprint {
     /// Called when a new blueprint is spawned, to fill out the entity with components
     fn fill_blueprint(&self, cmd: &mut EntityCommands);
     /// Called when the bounds need to be updated (like resizing from the editor)
-    fn sync_dimensions(&self, edit: &EditableCollider, cmd: &mut EntityCommands) {
-        cmd.insert(CollisionShape::Cuboid {
-            half_extends: edit.half_extends.extend(100.0),
-            border_radius: None,
-        });
+    fn sync_dimensions(&self, geometry: &ColliderGeometry, cmd: &mut EntityCommands) {
+        cmd.insert(geometry.collision_shape());
+    }
+    /// Called when the blueprint component itself was changed (like from the editor inspector)
+    fn sync_params(&self, _cmd: &mut EntityCommands) {
     }
 }
 
@@ -303,6 +566,10 @@ impl Blueprint for collider::Wall {
     const EDITOR_ID: &'static str = "Wall";
     const DEFAULT_Z: f32 = 0.0;
     type BlueprintBundle = ColliderBlueprintBundle<Self>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_collider::<Self>)
+            .with_system(collider_apply_sync::<Self>)
+    }
 }
 
 impl ColliderBehavior for collider::Wall {
@@ -323,6 +590,10 @@ impl Blueprint for collider::HurtZone {
     const EDITOR_ID: &'static str = "HurtZone";
     const DEFAULT_Z: f32 = 0.0;
     type BlueprintBundle = ColliderBlueprintBundle<Self>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_collider::<Self>)
+            .with_system(collider_apply_sync::<Self>)
+    }
 }
 
 impl ColliderBehavior for collider::HurtZone {
@@ -333,10 +604,11 @@ impl ColliderBehavior for collider::HurtZone {
             .insert(Pulsing::from(self));
     }
     /// HurtZones need a DamageAreaShape instead of CollisionShape
-    fn sync_dimensions(&self, edit: &EditableCollider, cmd: &mut EntityCommands) {
-        cmd.insert(DamageAreaShape::Cuboid {
-            half_extends: edit.half_extends.extend(100.0),
-        });
+    fn sync_dimensions(&self, geometry: &ColliderGeometry, cmd: &mut EntityCommands) {
+        cmd.insert(geometry.damage_area_shape());
+    }
+    fn sync_params(&self, cmd: &mut EntityCommands) {
+        cmd.insert(Pulsing::from(self));
     }
 }
 
@@ -344,6 +616,10 @@ impl Blueprint for collider::WinZone {
     const EDITOR_ID: &'static str = "WinZone";
     const DEFAULT_Z: f32 = 0.0;
     type BlueprintBundle = ColliderBlueprintBundle<Self>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_collider::<Self>)
+            .with_system(collider_apply_sync::<Self>)
+    }
 }
 
 impl ColliderBehavior for collider::WinZone {
@@ -365,6 +641,10 @@ impl Blueprint for collider::SpawnZone {
     const EDITOR_ID: &'static str = "SpawnZone";
     const DEFAULT_Z: f32 = 0.0;
     type BlueprintBundle = ColliderBlueprintBundle<Self>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_collider::<Self>)
+            .with_system(collider_apply_sync::<Self>)
+    }
 }
 
 impl ColliderBehavior for collider::SpawnZone {
@@ -375,7 +655,33 @@ impl ColliderBehavior for collider::SpawnZone {
             // TODO: add any other stuff needed
             .insert(GlobalTransform::default());
     }
-    fn sync_dimensions(&self, _edit: &EditableCollider, _cmd: &mut EntityCommands) {
+    fn sync_dimensions(&self, _geometry: &ColliderGeometry, _cmd: &mut EntityCommands) {
+    }
+}
+
+impl Blueprint for collider::Sensor {
+    const EDITOR_ID: &'static str = "Sensor";
+    const DEFAULT_Z: f32 = 0.0;
+    type BlueprintBundle = ColliderBlueprintBundle<Self>;
+    fn register_systems(sset: SystemSet) -> SystemSet {
+        sset.with_system(init_bp_collider::<Self>)
+            .with_system(collider_apply_sync::<Self>)
+    }
+}
+
+impl ColliderBehavior for collider::Sensor {
+    const KINDENUM: ColliderKind = ColliderKind::Sensor;
+    const EDITOR_COLOR: Color = Color::rgba(1.0, 1.0, 0.25, 0.25);
+    fn fill_blueprint(&self, cmd: &mut EntityCommands) {
+        // does nothing by itself; interacting in it interacts with its `TriggerTargets`
+        cmd.insert(GlobalTransform::default())
+            .insert(Trigger::default())
+            .insert(RigidBody::Sensor)
+            .insert(
+                CollisionLayers::none()
+                    .with_group(PhysLayer::PlayerTriggers)
+                    .with_masks(&[PhysLayer::Player]),
+            );
     }
 }
 
@@ -394,11 +700,27 @@ fn init_bp_collider<T: ColliderBehavior>(mut commands: Commands, q_bp: Blueprint
 }
 
 pub fn collider_apply_sync<T: ColliderBehavior>(
-    q: Query<(Entity, &T, &EditableCollider), (Changed<EditableCollider>,)>,
+    q: Query<(
+        Entity,
+        &T,
+        Option<&EditableCollider>,
+        Option<&EditableCircle>,
+        Option<&EditablePolygon>,
+    ), Or<(
+        Changed<EditableCollider>,
+        Changed<EditableCircle>,
+        Changed<EditablePolygon>,
+    )>>,
+    q_params: Query<(Entity, &T), Changed<T>>,
     mut cmd: Commands,
 ) {
-    for (e, coll, edit) in q.iter() {
-        coll.sync_dimensions(edit, &mut cmd.entity(e));
+    for (e, coll, edit, circle, polygon) in q.iter() {
+        if let Some(geometry) = ColliderGeometry::from_editable(edit, circle, polygon) {
+            coll.sync_dimensions(&geometry, &mut cmd.entity(e));
+        }
+    }
+    for (e, coll) in q_params.iter() {
+        coll.sync_params(&mut cmd.entity(e));
     }
 }
 
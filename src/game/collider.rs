@@ -3,7 +3,7 @@ use std::time::Duration;
 use bevy::{prelude::*, ecs::system::EntityCommands};
 use heron::CollisionShape;
 
-use crate::editor::collider::EditableCollider;
+use crate::editor::collider::{EditableCircle, EditableCollider, EditablePolygon};
 
 use super::{damage::{Pulsing, DamageAreaShape}, blueprints::ColliderBehavior};
 
@@ -13,8 +13,8 @@ pub struct Wall;
 #[derive(Component, Clone, Copy, Reflect)]
 #[reflect(Component)]
 pub struct HurtZone {
-    interval_secs: f32,
-    damage: f32,
+    pub interval_secs: f32,
+    pub damage: f32,
 }
 #[derive(Component, Default, Clone, Copy, Reflect)]
 #[reflect(Component)]
@@ -22,6 +22,10 @@ pub struct WinZone;
 #[derive(Component, Default, Clone, Copy, Reflect)]
 #[reflect(Component)]
 pub struct SpawnZone;
+/// A trigger with no behavior of its own, for interacting with other entities
+#[derive(Component, Default, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Sensor;
 
 #[derive(Component, Clone, Copy)]
 pub enum ColliderKind {
@@ -29,19 +33,135 @@ pub enum ColliderKind {
     HurtZone,
     WinZone,
     SpawnZone,
+    Sensor,
 }
 
 impl ColliderKind {
-    pub fn insert(&self, cmd: &mut EntityCommands) {
+    /// Can this kind of collider be a circle or polygon, rather than a box?
+    pub fn allows_shapes(&self) -> bool {
+        matches!(self, ColliderKind::Wall | ColliderKind::HurtZone | ColliderKind::Sensor)
+    }
+
+    /// Insert the blueprint component for this kind of collider
+    ///
+    /// `hurtzone` is the parameters to use, if this is a hurt zone (defaults otherwise).
+    pub fn insert(&self, cmd: &mut EntityCommands, hurtzone: Option<&HurtZone>) {
         match self {
             ColliderKind::Wall => cmd.insert(Wall),
-            ColliderKind::HurtZone => cmd.insert(HurtZone::default()), // FIXME editor cloning
+            ColliderKind::HurtZone => cmd.insert(hurtzone.copied().unwrap_or_default()),
             ColliderKind::WinZone => cmd.insert(WinZone),
             ColliderKind::SpawnZone => cmd.insert(SpawnZone),
+            ColliderKind::Sensor => cmd.insert(Sensor),
         };
     }
 }
 
+/// The shape of a collider blueprint, from whichever editable shape component it has
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColliderGeometry {
+    Cuboid { half_extends: Vec2 },
+    Circle { radius: f32 },
+    /// Convex; points are in the local space of the entity
+    Polygon { points: Vec<Vec2> },
+}
+
+/// Colliders reach this far along Z, so that they hit everything
+const COLLIDER_HALF_DEPTH: f32 = 100.0;
+const CIRCLE_SEGMENTS: usize = 24;
+
+impl ColliderGeometry {
+    pub fn from_editable(
+        cuboid: Option<&EditableCollider>,
+        circle: Option<&EditableCircle>,
+        polygon: Option<&EditablePolygon>,
+    ) -> Option<Self> {
+        if let Some(circle) = circle {
+            Some(ColliderGeometry::Circle { radius: circle.radius })
+        } else if let Some(polygon) = polygon {
+            Some(ColliderGeometry::Polygon { points: polygon.points.clone() })
+        } else {
+            cuboid.map(|edit| ColliderGeometry::Cuboid { half_extends: edit.half_extends })
+        }
+    }
+
+    /// Can these points be used for a polygon collider as they are?
+    ///
+    /// Physics always uses their convex hull, so anything else would collide
+    /// differently from how it looks in the editor.
+    pub fn is_convex(points: &[Vec2]) -> bool {
+        let n = points.len();
+        if n < 3 {
+            return false;
+        }
+        let mut sign = 0.0;
+        let mut turned = 0.0;
+        for i in 0..n {
+            let a = points[(i + 1) % n] - points[i];
+            let b = points[(i + 2) % n] - points[(i + 1) % n];
+            let cross = a.perp_dot(b);
+            if cross != 0.0 {
+                if sign == 0.0 {
+                    sign = cross.signum();
+                } else if cross.signum() != sign {
+                    return false;
+                }
+            }
+            turned += a.angle_between(b);
+        }
+        // turning the same way all the time, but more than once around, is a star
+        turned.abs() < std::f32::consts::TAU * 1.5
+    }
+
+    /// Points of a convex hull that is extruded along Z
+    fn hull_points(points: &[Vec2]) -> Vec<Vec3> {
+        points.iter()
+            .flat_map(|p| [p.extend(-COLLIDER_HALF_DEPTH), p.extend(COLLIDER_HALF_DEPTH)])
+            .collect()
+    }
+
+    /// Circles are approximated with a polygon; a sphere would get smaller
+    /// away from Z=0, and physics has no cylinder along Z
+    pub fn circle_points(radius: f32) -> Vec<Vec2> {
+        (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                Vec2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect()
+    }
+
+    pub fn collision_shape(&self) -> CollisionShape {
+        match self {
+            ColliderGeometry::Cuboid { half_extends } => CollisionShape::Cuboid {
+                half_extends: half_extends.extend(COLLIDER_HALF_DEPTH),
+                border_radius: None,
+            },
+            ColliderGeometry::Circle { radius } => CollisionShape::ConvexHull {
+                points: Self::hull_points(&Self::circle_points(*radius)),
+                border_radius: None,
+            },
+            ColliderGeometry::Polygon { points } => CollisionShape::ConvexHull {
+                points: Self::hull_points(points),
+                border_radius: None,
+            },
+        }
+    }
+
+    pub fn damage_area_shape(&self) -> DamageAreaShape {
+        match self {
+            ColliderGeometry::Cuboid { half_extends } => DamageAreaShape::Cuboid {
+                half_extends: half_extends.extend(COLLIDER_HALF_DEPTH),
+            },
+            ColliderGeometry::Circle { radius } => DamageAreaShape::ConvexHull {
+                points: Self::hull_points(&Self::circle_points(*radius)),
+            },
+            ColliderGeometry::Polygon { points } => DamageAreaShape::ConvexHull {
+                points: Self::hull_points(points),
+            },
+        }
+    }
+}
+
 impl From<&HurtZone> for Pulsing {
     fn from(hz: &HurtZone) -> Self {
         Pulsing {
@@ -41,6 +41,7 @@ pub struct Pulsing {
 pub enum DamageAreaShape {
     Cuboid { half_extends: Vec3 },
     Sphere { radius: f32 },
+    ConvexHull { points: Vec<Vec3> },
 }
 
 impl From<&DamageAreaShape> for CollisionShape {
@@ -51,6 +52,10 @@ impl From<&DamageAreaShape> for CollisionShape {
                 border_radius: None,
             },
             DamageAreaShape::Sphere { radius } => CollisionShape::Sphere { radius: *radius },
+            DamageAreaShape::ConvexHull { points } => CollisionShape::ConvexHull {
+                points: points.clone(),
+                border_radius: None,
+            },
         }
     }
 }
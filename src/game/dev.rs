@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use bevy::prelude::*;
 use bevy_asset_loader::{AssetCollection, AssetLoader};
 use bevy_kira_audio::AudioSource;
@@ -6,8 +8,10 @@ use iyes_bevy_util::BevyState;
 
 use crate::game::phys_layers::PhysLayer;
 use crate::game::timer::GameTimer;
+use crate::game::tiled::TiledLevelBundle;
+use crate::scene_exporter::{asset_fs_path, SaveScenePath};
 
-use super::GameCleanup;
+use super::{spawn_scenario_scene, GameCleanup};
 
 /// This plugin should add all DevPlayground specific stuff
 pub struct DevPlaygroundPlugin<S: BevyState + Copy> {
@@ -27,10 +31,11 @@ impl<S: BevyState + Copy> Plugin for DevPlaygroundPlugin<S> {
         // add systems to `self.state`
         app.add_system_set(
             SystemSet::on_enter(self.state)
-                .with_system(spawn_dynamic_scene)
+                .with_system(spawn_dynamic_scene.exclusive_system())
+                .with_system(set_save_path)
                 .with_system(init_game_timer)
                 .with_system(setup_scene)
-                //.with_system(debug_spawn_door)
+                .with_system(spawn_dev_map)
                 //.with_system(debug_environment_damage_zones)
         );
         app.add_system_set(
@@ -53,6 +58,16 @@ pub struct DevAssets {
 	pub generator: Handle<AudioSource>,
 }
 
+/// Tiled map for trying out the importer, spawned if it exists
+const DEV_MAP: &str = "maps/dev.tmx";
+
+fn spawn_dev_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+    if !asset_fs_path(Path::new(DEV_MAP)).exists() {
+        return;
+    }
+    commands.spawn_bundle(TiledLevelBundle::new(asset_server.load(DEV_MAP)));
+}
+
 fn init_game_timer(
     mut commands: Commands,
 ) {
@@ -61,11 +76,20 @@ fn init_game_timer(
     commands.insert_resource(GameTimer(timer));
 }
 
-fn spawn_dynamic_scene(
-    mut scene_spawner: ResMut<SceneSpawner>,
+fn spawn_dynamic_scene(world: &mut World) {
+    let scene = world.get_resource::<DevAssets>().unwrap().scene.clone();
+    spawn_scenario_scene(world, &scene);
+}
+
+/// Make the editor save back to the file the scenario was loaded from
+fn set_save_path(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
     assets: Res<DevAssets>,
 ) {
-    scene_spawner.spawn_dynamic(assets.scene.clone());
+    if let Some(path) = asset_server.get_handle_path(&assets.scene) {
+        commands.insert_resource(SaveScenePath(path.path().to_owned()));
+    }
 }
 
 fn setup_scene(mut commands: Commands, assets: Res<DevAssets>) {
@@ -31,6 +31,18 @@ pub struct EnemyConfig {
     pub timer_slow: Timer,
 }
 
+impl EnemyConfig {
+    /// Account for an enemy spawned from outside of `spawn_zones`
+    pub fn add_spawned(&mut self) {
+        self.count += 1;
+    }
+
+    /// Account for all enemies having been despawned from outside of the enemy systems
+    pub fn forget_spawned(&mut self) {
+        self.count = 0;
+    }
+}
+
 impl Default for EnemyConfig {
     fn default() -> Self {
         EnemyConfig {
@@ -119,7 +131,7 @@ pub struct EnemyBundle {
     target_last_seen: EnemyTargetLastSeen,
     scanning: EnemyTargetScanning,
     stuck: EnemyStuckDetect,
-    health: Health,
+    pub health: Health,
     // physics
     rigidbody: RigidBody,
     phys_layers: CollisionLayers,
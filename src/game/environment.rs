@@ -1,6 +1,7 @@
 use crate::game::collider::WinZone;
 use crate::game::player::Player;
 use crate::game::GameResult;
+use crate::scene_exporter::stable_id::{EntityRef, EntityRefs};
 use crate::AppState;
 use bevy::prelude::*;
 use heron::prelude::*;
@@ -20,6 +21,24 @@ pub struct Trigger {
     pub entities: Vec<Entity>,
 }
 
+/// Other scene entities that a trigger interacts with, as well as its own `entities`
+///
+/// Unlike those, these are saved with the scene.
+#[derive(Component, Default, Clone, Reflect)]
+#[reflect(Component)]
+pub struct TriggerTargets {
+    pub targets: Vec<EntityRef>,
+}
+
+impl EntityRefs for TriggerTargets {
+    fn entity_refs(&self) -> &[EntityRef] {
+        &self.targets
+    }
+    fn entity_refs_mut(&mut self) -> &mut [EntityRef] {
+        &mut self.targets
+    }
+}
+
 #[derive(Component)]
 pub struct TriggerTimeout {
     pub timeout: Timer,
@@ -68,10 +87,10 @@ pub fn trigger_player_detection(
 pub fn trigger_interaction(
     input: Res<Input<KeyCode>>,
     mut interation_events: EventWriter<InterationEvent>,
-    query_triggers: Query<(&Trigger, Option<&TriggerTimeout>)>,
+    query_triggers: Query<(&Trigger, Option<&TriggerTimeout>, Option<&TriggerTargets>)>,
 ) {
     if input.just_pressed(KeyCode::E) {
-        for (trigger, timeout) in query_triggers.iter() {
+        for (trigger, timeout, targets) in query_triggers.iter() {
             if let Some(timeout) = timeout {
                 if !timeout.timeout.finished() {
                     continue;
@@ -81,6 +100,10 @@ pub fn trigger_interaction(
                 for entity in trigger.entities.iter() {
                     interation_events.send(InterationEvent { entity: *entity });
                 }
+                let targets = targets.iter().flat_map(|t| t.targets.iter());
+                for entity in targets.filter_map(|target| target.entity()) {
+                    interation_events.send(InterationEvent { entity });
+                }
             }
         }
     };
@@ -1,5 +1,47 @@
-use crate::game::damage::{Health, Pulsing};
+use crate::game::damage::{DamageAreaShape, Health, Pulsing, PulsingBundle};
 use bevy::prelude::*;
+use heron::CollisionShape;
+
+/// The explosive barrel blueprint, placed in the editor
+///
+/// Explodes when shot down, damaging everything within the blast radius
+/// (including other barrels).
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct ExplosiveBarrel {
+    pub health: f32,
+    pub blast_radius: f32,
+    pub blast_damage: f32,
+}
+
+impl Default for ExplosiveBarrel {
+    fn default() -> Self {
+        Self {
+            health: 30.0,
+            blast_radius: 150.0,
+            blast_damage: 60.0,
+        }
+    }
+}
+
+/// Delay between the barrel being shot down and the blast doing damage
+const BARREL_FUSE: f32 = 0.1;
+
+/// The blast of a barrel: a one-shot pulse, paused until the barrel is shot down
+pub fn barrel_blast(barrel: &ExplosiveBarrel) -> PulsingBundle {
+    let mut pulse_time = Timer::from_seconds(BARREL_FUSE, false);
+    pulse_time.pause();
+    PulsingBundle {
+        pulsing: Pulsing {
+            pulse_time,
+            damage: barrel.blast_damage,
+        },
+        damage_area_shape: DamageAreaShape::Sphere {
+            radius: barrel.blast_radius,
+        },
+        ..Default::default()
+    }
+}
 
 #[derive(Component, Debug)]
 pub enum ExplosiveObjectState {
@@ -24,6 +66,8 @@ pub fn explosive_objects_controller(
                 if health.current <= 0.0 {
                     *state = ExplosiveObjectState::Exploding(Timer::from_seconds(1.5, false));
                     pulsing.pulse_time.unpause();
+                    // the wreck shouldn't block anything
+                    commands.entity(entity).remove::<CollisionShape>();
                 }
             }
             ExplosiveObjectState::Exploding(timer) => {
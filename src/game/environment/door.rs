@@ -2,36 +2,197 @@ use super::{InterationEvent, Trigger};
 use crate::game::phys_layers::PhysLayer;
 use bevy::prelude::*;
 use heron::{CollisionLayers, CollisionShape, RigidBody};
+use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+#[reflect_value(PartialEq, Serialize, Deserialize)]
 pub enum DoorOpenStatus {
     Opened,
     Closed,
 }
 
+impl Default for DoorOpenStatus {
+    fn default() -> Self {
+        DoorOpenStatus::Closed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+#[reflect_value(PartialEq, Serialize, Deserialize)]
 pub enum DoorLockStatus {
     Locked,
     Unlocked,
 }
 
+impl Default for DoorLockStatus {
+    fn default() -> Self {
+        DoorLockStatus::Unlocked
+    }
+}
+
+/// Which end of the door the hinge is at, looking at its front
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+#[reflect_value(PartialEq, Serialize, Deserialize)]
 pub enum DoorRotationSide {
     Right,
     Left,
 }
 
-#[derive(Component)]
+impl Default for DoorRotationSide {
+    fn default() -> Self {
+        DoorRotationSide::Left
+    }
+}
+
+/// The door blueprint, placed in the editor
+///
+/// The entity itself stays where it was placed (the closed position);
+/// the moving panel and the interaction sensors are generated as children,
+/// sized from its `EditableCollider`. Its front is local -X.
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
 pub struct Door {
+    pub rotation_side: DoorRotationSide,
+    pub lock_status: DoorLockStatus,
+    /// Whether the door starts open
+    pub open_status: DoorOpenStatus,
+}
+
+/// The part of a door that opens and closes
+#[derive(Component)]
+pub struct DoorPanel {
     pub front: Vec3,
     pub open_status: DoorOpenStatus,
     pub lock_status: DoorLockStatus,
     pub rotation_side: DoorRotationSide,
 }
 
+/// Generated by `spawn_door_parts`, as opposed to children placed by a designer
+#[derive(Component)]
+pub struct DoorPart;
+
+/// How far in front of and behind the door the player can open it from
+const DOOR_SENSOR_DEPTH: f32 = 20.0;
+const DOOR_COLOR: Color = Color::rgb(1.0, 0.2, 1.0);
+
+/// (Re)generate the panel and sensors of a door, for its current size and settings
+///
+/// `parts` are the `DoorPart`s among the children of the door, to be replaced;
+/// any other children are left alone.
+pub fn spawn_door_parts(
+    commands: &mut Commands,
+    e: Entity,
+    door: &Door,
+    half_extends: Vec2,
+    parts: impl Iterator<Item = Entity>,
+) {
+    for part in parts {
+        commands.entity(part).despawn_recursive();
+    }
+
+    let mut panel = DoorPanel {
+        front: -Vec3::X,
+        open_status: DoorOpenStatus::Closed,
+        lock_status: DoorLockStatus::Unlocked,
+        rotation_side: door.rotation_side,
+    };
+    let mut panel_xf = Transform::identity();
+    if door.open_status == DoorOpenStatus::Opened {
+        toggle_door(&mut panel, &mut panel_xf, half_extends.y);
+    }
+    panel.lock_status = door.lock_status;
+
+    let panel = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(half_extends * 2.0),
+                color: DOOR_COLOR,
+                ..Default::default()
+            },
+            transform: panel_xf,
+            ..Default::default()
+        })
+        .insert(panel)
+        .insert(DoorPart)
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(CollisionShape::Cuboid {
+            half_extends: half_extends.extend(0.1),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(PhysLayer::World)
+                .with_masks(&[PhysLayer::Player, PhysLayer::Enemies, PhysLayer::Bullets]),
+        )
+        .id();
+    commands.entity(e).push_children(&[panel]);
+
+    // one on each side
+    for side in [-1.0, 1.0] {
+        let offset = Vec3::X * side * (half_extends.x + DOOR_SENSOR_DEPTH / 2.0);
+        let sensor = commands.spawn()
+            .insert(Transform::from_translation(offset))
+            .insert(GlobalTransform::default())
+            .insert(DoorPart)
+            .insert(Trigger {
+                player_detected: false,
+                entities: vec![panel],
+            })
+            .insert(RigidBody::Sensor)
+            .insert(CollisionShape::Cuboid {
+                half_extends: Vec3::new(DOOR_SENSOR_DEPTH / 2.0, half_extends.y, 0.1),
+                border_radius: None,
+            })
+            .insert(CollisionLayers::new(PhysLayer::PlayerTriggers, PhysLayer::Player))
+            .id();
+        commands.entity(e).push_children(&[sensor]);
+    }
+}
+
+/// Swing the door panel open or closed around its hinge
+fn toggle_door(door: &mut DoorPanel, transform: &mut Transform, half_door_len: f32) {
+    let (axis_rotation, door_rotation) = match door.open_status {
+        DoorOpenStatus::Opened => {
+            door.open_status = DoorOpenStatus::Closed;
+            match door.rotation_side {
+                DoorRotationSide::Left => (1.0, -1.0),
+                DoorRotationSide::Right => (-1.0, 1.0),
+            }
+        }
+        DoorOpenStatus::Closed => {
+            door.open_status = DoorOpenStatus::Opened;
+            match door.rotation_side {
+                DoorRotationSide::Left => (-1.0, -1.0),
+                DoorRotationSide::Right => (1.0, 1.0),
+            }
+        }
+    };
+    let to_side = Quat::from_rotation_z(axis_rotation * 90.0_f32.to_radians())
+        .mul_vec3(door.front);
+    let axis_pos = transform.translation + to_side * half_door_len;
+    let to_new_pos = Quat::from_rotation_z(-door_rotation * 90.0_f32.to_radians())
+        .mul_vec3(-to_side);
+    transform.translation = axis_pos + to_new_pos * half_door_len;
+    transform.rotate(Quat::from_rotation_z(door_rotation * 90.0_f32.to_radians()));
+}
+
 pub fn door_interaction(
     mut interaction_events: EventReader<InterationEvent>,
-    mut doors: Query<(&CollisionShape, &mut Door, &mut Transform)>,
+    q_blueprints: Query<&Children, With<Door>>,
+    mut doors: Query<(&CollisionShape, &mut DoorPanel, &mut Transform)>,
 ) {
     for interaction in interaction_events.iter() {
-        if let Ok((collision_shape, mut door, mut transform)) = doors.get_mut(interaction.entity) {
+        // other triggers refer to the door blueprint, rather than its panel
+        let panel = match q_blueprints.get(interaction.entity) {
+            Ok(children) => children.iter().copied().find(|e| doors.get(*e).is_ok()),
+            Err(_) => Some(interaction.entity),
+        };
+        let panel = if let Some(panel) = panel {
+            panel
+        } else {
+            continue;
+        };
+        if let Ok((collision_shape, mut door, mut transform)) = doors.get_mut(panel) {
             let half_door_len = match collision_shape {
                 CollisionShape::Cuboid { half_extends, .. } => half_extends[1],
                 _ => {
@@ -44,112 +205,9 @@ pub fn door_interaction(
                     debug!("door is locked");
                 }
                 DoorLockStatus::Unlocked => {
-                    let (axis_rotation, door_rotation) = match door.open_status {
-                        DoorOpenStatus::Opened => {
-                            door.open_status = DoorOpenStatus::Closed;
-                            match door.rotation_side {
-                                DoorRotationSide::Left => (1.0, -1.0),
-                                DoorRotationSide::Right => (-1.0, 1.0),
-                            }
-                        }
-                        DoorOpenStatus::Closed => {
-                            door.open_status = DoorOpenStatus::Opened;
-                            match door.rotation_side {
-                                DoorRotationSide::Left => (-1.0, -1.0),
-                                DoorRotationSide::Right => (1.0, 1.0),
-                            }
-                        }
-                    };
-                    let to_side = Quat::from_rotation_z(axis_rotation * 90.0_f32.to_radians())
-                        .mul_vec3(door.front);
-                    let axis_pos = transform.translation + to_side * half_door_len;
-                    let to_new_pos = Quat::from_rotation_z(-door_rotation * 90.0_f32.to_radians())
-                        .mul_vec3(-to_side);
-                    transform.translation = axis_pos + to_new_pos * half_door_len;
-                    transform.rotate(Quat::from_rotation_z(door_rotation * 90.0_f32.to_radians()));
+                    toggle_door(&mut door, &mut transform, half_door_len);
                 }
             }
         }
     }
 }
-
-pub fn debug_spawn_door(mut commands: Commands) {
-    let door_pos = Vec3::new(437.0, 136.0, 0.0);
-    // door
-    let door = commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(10.0, 100.0)),
-                color: Color::rgb(1.0, 0.2, 1.0),
-                ..Default::default()
-            },
-            transform: Transform::from_translation(door_pos),
-            ..Default::default()
-        })
-        .insert(Door {
-            front: -Vec3::X,
-            open_status: DoorOpenStatus::Closed,
-            lock_status: DoorLockStatus::Unlocked,
-            rotation_side: DoorRotationSide::Left,
-        })
-        .insert(RigidBody::KinematicPositionBased)
-        .insert(CollisionShape::Cuboid {
-            half_extends: Vec3::new(5.0, 50.0, 0.1),
-            border_radius: None,
-        })
-        .id();
-
-    // sensors
-    let sensor1_pos = Vec3::new(400.0, 136.0, 0.0);
-    commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(20.0, 20.0)),
-                color: Color::rgb(1.0, 0.2, 1.0),
-                ..Default::default()
-            },
-            transform: Transform::from_translation(sensor1_pos),
-            ..Default::default()
-        })
-        .insert(Trigger {
-            player_detected: false,
-            entities: vec![door],
-        })
-        .insert(RigidBody::Sensor)
-        .insert(CollisionShape::Cuboid {
-            half_extends: Vec3::new(10.0, 10.0, 0.1),
-            border_radius: None,
-        })
-        .insert(
-            CollisionLayers::new(PhysLayer::PlayerTriggers, PhysLayer::Player)
-                // .with_group(PhysLayer::PlayerTriggers)
-                // .with_masks(&[PhysLayer::Player]),
-        );
-
-    let sensor2_pos = Vec3::new(480.0, 136.0, 0.0);
-    commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(20.0, 20.0)),
-                color: Color::rgb(1.0, 0.2, 1.0),
-                ..Default::default()
-            },
-            transform: Transform::from_translation(sensor2_pos),
-            ..Default::default()
-        })
-        .insert(Trigger {
-            player_detected: false,
-            entities: vec![door],
-        })
-        .insert(RigidBody::Sensor)
-        .insert(CollisionShape::Cuboid {
-            half_extends: Vec3::new(10.0, 10.0, 0.1),
-            border_radius: None,
-        })
-        .insert(
-            CollisionLayers::new(PhysLayer::PlayerTriggers, PhysLayer::Player)
-            // CollisionLayers::none()
-            //     .with_group(PhysLayer::PlayerTriggers)
-            //     .with_masks(&[PhysLayer::Player]),
-        );
-}
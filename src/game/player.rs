@@ -1,13 +1,17 @@
 use super::animations::{AnimationBundle, AnimationPausableBundle};
 use super::shooting::WeaponryBundle;
-use super::SpatialAudioReceptor;
+use super::{ScenarioSceneSpawned, SpatialAudioReceptor};
 use crate::game::damage::Health;
 use crate::game::phys_layers::PhysLayer;
 use crate::util::WorldCursor;
+use crate::game::blueprints::PlayerStart;
+use crate::game::savegame::PendingRestore;
+use crate::scene_exporter::SaveSceneMarker;
 use crate::AppState;
 use bevy::prelude::*;
 use heron::rapier_plugin::{PhysicsWorld, ShapeCastCollisionType};
 use heron::{CollisionLayers, CollisionShape, RigidBody};
+use rand::prelude::*;
 
 #[derive(Component)]
 pub struct Player;
@@ -76,8 +80,12 @@ impl Default for PlayerBundle {
     }
 }
 
+/// Present until the player has been moved to a `PlayerStart`
+pub struct PlayerStartPending;
+
 pub fn init_player(mut commands: Commands) {
-    let player_transform = Transform::from_translation(Vec3::new(-2982.9265, 1052.7454, 2.0));
+    // moved to a `PlayerStart` once the scene has spawned
+    let player_transform = Transform::from_translation(Vec3::new(0.0, 0.0, 2.0));
     let _x = commands
         .spawn_bundle(AnimationBundle::default_with_transform_size(
             player_transform,
@@ -108,6 +116,51 @@ pub fn init_player(mut commands: Commands) {
         });
 }
 
+pub fn init_player_start(mut commands: Commands, pending: Option<Res<PendingRestore>>) {
+    // a continued game puts the player back where it was saved instead
+    if pending.is_none() {
+        commands.insert_resource(PlayerStartPending);
+    }
+}
+
+/// Move the player to a `PlayerStart` (a random one, if there are several)
+///
+/// Decided once, as soon as the scenario's scene has been spawned; coming
+/// back from the editor never moves the player. A scene without any
+/// `PlayerStart` is an error; we go back to the main menu.
+pub fn place_player_at_start(
+    mut commands: Commands,
+    pending: Option<Res<PlayerStartPending>>,
+    spawned: Option<Res<ScenarioSceneSpawned>>,
+    mut state: ResMut<State<AppState>>,
+    q_start: Query<&Transform, (With<PlayerStart>, Without<Player>)>,
+    q_scene: Query<(), With<SaveSceneMarker>>,
+    mut q_player: Query<&mut Transform, With<Player>>,
+) {
+    if pending.is_none() || spawned.is_none() {
+        return;
+    }
+    let mut xf = if let Ok(xf) = q_player.get_single_mut() {
+        xf
+    } else {
+        return;
+    };
+    commands.remove_resource::<PlayerStartPending>();
+
+    if let Some(start) = q_start.iter().choose(&mut thread_rng()) {
+        xf.translation.x = start.translation.x;
+        xf.translation.y = start.translation.y;
+        xf.rotation = start.rotation;
+    } else {
+        if q_scene.iter().next().is_none() {
+            error!("The scenario's scene is empty; did it fail to load?");
+        } else {
+            error!("The scenario has no PlayerStart; place one with the editor");
+        }
+        state.replace(AppState::MainMenu).ok();
+    }
+}
+
 pub fn print_player_position(q: Query<&Transform, With<Player>>, keys: Res<Input<KeyCode>>) {
     if keys.just_pressed(KeyCode::P) {
         let t = q.single();
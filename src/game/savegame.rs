@@ -0,0 +1,398 @@
+//! Save games: the state of a run in progress, so that it can be continued later
+//!
+//! This is separate from scenes: the scene describes the level as authored,
+//! the save game describes what has happened to it during the run. When
+//! continuing, the scenario is started as usual (spawning its scene), and
+//! then the saved state is applied on top. Entities from the scene (like
+//! pickups) are matched up by their `StableId`.
+//!
+//! Saving happens on F5 and when quitting to the main menu.
+//! The save game is deleted when the run ends (win or lose).
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::asset::FileAssetIo;
+use bevy::ecs::system::{System, SystemState};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use iyes_bevy_util::remove_resource;
+use serde::{Deserialize, Serialize};
+
+use crate::game::animations::EnemyAnimations;
+use crate::game::blueprints::{AmmoBox, Medkit};
+use crate::game::damage::Health;
+use crate::game::enemies::{Enemy, EnemyBundle, EnemyConfig};
+use crate::game::environment::barrel::{ExplosiveBarrel, ExplosiveObjectState};
+use crate::game::environment::{MultiUse, ReadyToDespawn};
+use crate::game::player::Player;
+use crate::game::shooting::{SpareAmmo, WeaponMagazine};
+use crate::game::timer::GameTimer;
+use crate::scene_exporter::stable_id::StableId;
+use crate::{AppState, GameMode};
+
+const SAVEGAME_FILE: &str = "savegame.ron";
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub mode: GameMode,
+    pub player: SavedPlayer,
+    /// Seconds elapsed on the `GameTimer`
+    pub timer_elapsed: f32,
+    pub enemies: Vec<SavedEnemy>,
+    /// Pickups (and anything else that is `MultiUse`) that have been used
+    #[serde(default)]
+    pub used_pickups: Vec<SavedPickup>,
+    /// `StableId`s of the barrels that have been blown up
+    #[serde(default)]
+    pub destroyed_barrels: Vec<u64>,
+}
+
+/// Scene entities are identified by their `StableId`
+#[derive(Serialize, Deserialize)]
+pub struct SavedPickup {
+    pub id: u64,
+    /// Zero if it has been used up
+    pub remaining: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub position: [f32; 3],
+    pub rotation: f32,
+    pub health: f32,
+    pub magazine: i32,
+    pub spare_ammo: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedEnemy {
+    pub position: [f32; 3],
+    pub rotation: f32,
+    pub health: f32,
+}
+
+/// Present while the run is not a real one (like testing from the editor):
+/// the save game is neither written nor deleted
+pub struct SavingSuspended;
+
+/// Inserted when continuing a saved game; applied once the game has started
+pub struct PendingRestore(pub SaveGame);
+
+/// Uses left of the pickups that have been used during the current run
+#[derive(Default)]
+pub struct UsedPickups(HashMap<StableId, i32>);
+
+impl UsedPickups {
+    fn from_saved(saved: &[SavedPickup]) -> Self {
+        UsedPickups(saved.iter().map(|p| (StableId(p.id), p.remaining)).collect())
+    }
+}
+
+/// Barrels that have been blown up during the current run
+#[derive(Default)]
+pub struct DestroyedBarrels(HashSet<StableId>);
+
+impl DestroyedBarrels {
+    fn from_saved(saved: &[u64]) -> Self {
+        DestroyedBarrels(saved.iter().map(|id| StableId(*id)).collect())
+    }
+}
+
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::GameOver)
+                .with_system(delete_savegame)
+        );
+        app.add_system_set(
+            SystemSet::on_enter(AppState::MainMenu)
+                .with_system(remove_resource::<PendingRestore>)
+                .with_system(remove_resource::<SavingSuspended>)
+        );
+    }
+}
+
+fn savegame_path() -> PathBuf {
+    FileAssetIo::get_root_path().join(SAVEGAME_FILE)
+}
+
+pub fn savegame_exists() -> bool {
+    savegame_path().exists()
+}
+
+pub fn read_savegame() -> Result<SaveGame, String> {
+    let path = savegame_path();
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read save game {:?}: {}", path, e))?;
+    ron::from_str(&text)
+        .map_err(|e| format!("Could not parse save game {:?}: {}", path, e))
+}
+
+fn delete_savegame(suspended: Option<Res<SavingSuspended>>) {
+    if suspended.is_some() {
+        return;
+    }
+    let path = savegame_path();
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            error!("Could not delete save game {:?}: {}", path, e);
+        }
+    }
+}
+
+fn angle_z(rotation: Quat) -> f32 {
+    let (axis, angle) = rotation.to_axis_angle();
+    angle * axis.z.signum()
+}
+
+type SavedPlayerQuery<'w, 's> = Query<'w, 's,
+    (&'static Transform, &'static Health, &'static WeaponMagazine, &'static SpareAmmo),
+    With<Player>,
+>;
+type SavedEnemyQuery<'w, 's> = Query<'w, 's, (&'static Transform, &'static Health), With<Enemy>>;
+
+fn build_savegame(
+    mode: GameMode,
+    timer: &GameTimer,
+    used: &UsedPickups,
+    destroyed: &DestroyedBarrels,
+    q_player: &SavedPlayerQuery,
+    q_enemy: &SavedEnemyQuery,
+) -> Option<SaveGame> {
+    let (xf, health, magazine, spare) = match q_player.get_single() {
+        Ok(player) => player,
+        Err(e) => {
+            error!("Cannot save the game without exactly one player: {}", e);
+            return None;
+        }
+    };
+    Some(SaveGame {
+        mode,
+        player: SavedPlayer {
+            position: xf.translation.to_array(),
+            rotation: angle_z(xf.rotation),
+            health: health.current,
+            magazine: magazine.current,
+            spare_ammo: spare.current,
+        },
+        timer_elapsed: timer.0.elapsed_secs(),
+        enemies: q_enemy.iter().map(|(xf, health)| SavedEnemy {
+            position: xf.translation.to_array(),
+            rotation: angle_z(xf.rotation),
+            health: health.current,
+        }).collect(),
+        used_pickups: used.0.iter().map(|(id, remaining)| SavedPickup {
+            id: id.0,
+            remaining: *remaining,
+        }).collect(),
+        destroyed_barrels: destroyed.0.iter().map(|id| id.0).collect(),
+    })
+}
+
+/// Save on F5, and when quitting to the main menu
+pub fn save_game_hotkeys(
+    kbd: Res<Input<KeyCode>>,
+    mode: Res<GameMode>,
+    timer: Res<GameTimer>,
+    used: Res<UsedPickups>,
+    destroyed: Res<DestroyedBarrels>,
+    q_player: SavedPlayerQuery,
+    q_enemy: SavedEnemyQuery,
+    suspended: Option<Res<SavingSuspended>>,
+) {
+    if !kbd.just_pressed(KeyCode::F5) && !kbd.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    if suspended.is_some() {
+        info!("Not saving: this is a test run");
+        return;
+    }
+
+    let save = match build_savegame(*mode, &timer, &used, &destroyed, &q_player, &q_enemy) {
+        Some(save) => save,
+        None => return,
+    };
+
+    let path = savegame_path();
+    let result = ron::ser::to_string_pretty(&save, Default::default())
+        .map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(&path, text).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => info!("Game saved to {:?}", path),
+        Err(e) => error!("Could not save game to {:?}: {}", path, e),
+    }
+}
+
+/// Start tracking used pickups (from the save game, if continuing)
+pub fn init_used_pickups(
+    mut commands: Commands,
+    pending: Option<Res<PendingRestore>>,
+) {
+    let used = pending
+        .map(|pending| UsedPickups::from_saved(&pending.0.used_pickups))
+        .unwrap_or_default();
+    commands.insert_resource(used);
+}
+
+pub fn record_used_pickups(
+    mut used: ResMut<UsedPickups>,
+    q_used: Query<(&StableId, &MultiUse, ChangeTrackers<MultiUse>), Changed<MultiUse>>,
+    q_gone: Query<&StableId, (Added<ReadyToDespawn>, Or<(With<Medkit>, With<AmmoBox>)>)>,
+) {
+    for (id, multi_use, tracker) in q_used.iter() {
+        // just spawned, not used
+        if tracker.is_added() {
+            continue;
+        }
+        used.0.insert(*id, multi_use.remaining);
+    }
+    for id in q_gone.iter() {
+        used.0.insert(*id, 0);
+    }
+}
+
+/// Pickups spawned from the scene that were already used in the saved run
+pub fn restore_used_pickups(
+    mut commands: Commands,
+    used: Res<UsedPickups>,
+    mut q: Query<(Entity, &StableId, Option<&mut MultiUse>), Added<StableId>>,
+) {
+    for (e, id, multi_use) in q.iter_mut() {
+        let remaining = if let Some(remaining) = used.0.get(id) {
+            *remaining
+        } else {
+            continue;
+        };
+        if remaining <= 0 {
+            commands.entity(e).insert(ReadyToDespawn);
+        } else if let Some(mut multi_use) = multi_use {
+            multi_use.remaining = remaining;
+        }
+    }
+}
+
+/// Start tracking destroyed barrels (from the save game, if continuing)
+pub fn init_destroyed_barrels(
+    mut commands: Commands,
+    pending: Option<Res<PendingRestore>>,
+) {
+    let destroyed = pending
+        .map(|pending| DestroyedBarrels::from_saved(&pending.0.destroyed_barrels))
+        .unwrap_or_default();
+    commands.insert_resource(destroyed);
+}
+
+/// A barrel counts as destroyed as soon as it goes off
+pub fn record_destroyed_barrels(
+    mut destroyed: ResMut<DestroyedBarrels>,
+    q: Query<(&StableId, &ExplosiveObjectState), (With<ExplosiveBarrel>, Changed<ExplosiveObjectState>)>,
+) {
+    for (id, state) in q.iter() {
+        if !matches!(state, ExplosiveObjectState::NotDetonated) {
+            destroyed.0.insert(*id);
+        }
+    }
+}
+
+/// Barrels spawned from the scene that were already blown up in the saved run
+pub fn restore_destroyed_barrels(
+    mut commands: Commands,
+    destroyed: Res<DestroyedBarrels>,
+    q: Query<(Entity, &StableId), (With<ExplosiveBarrel>, Added<StableId>)>,
+) {
+    for (e, id) in q.iter() {
+        if destroyed.0.contains(id) {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+/// Apply the saved state, once the player and timer exist
+#[allow(clippy::too_many_arguments)]
+pub fn apply_pending_restore(
+    mut commands: Commands,
+    pending: Option<Res<PendingRestore>>,
+    timer: Option<ResMut<GameTimer>>,
+    mut cfg: ResMut<EnemyConfig>,
+    animations: Option<Res<EnemyAnimations>>,
+    mut q_player: Query<(&mut Transform, &mut Health, &mut WeaponMagazine, &mut SpareAmmo), With<Player>>,
+) {
+    let (pending, mut timer, animations) = match (pending, timer, animations) {
+        (Some(pending), Some(timer), Some(animations)) => (pending, timer, animations),
+        _ => return,
+    };
+    let (mut xf, mut health, mut magazine, mut spare) = match q_player.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let save = &pending.0;
+
+    xf.translation = Vec3::from(save.player.position);
+    xf.rotation = Quat::from_rotation_z(save.player.rotation);
+    health.current = save.player.health;
+    magazine.current = save.player.magazine;
+    spare.current = save.player.spare_ammo;
+
+    timer.0.set_elapsed(Duration::from_secs_f32(save.timer_elapsed));
+
+    for enemy in save.enemies.iter() {
+        let mut enemy_xf = Transform::from_translation(Vec3::from(enemy.position));
+        enemy_xf.rotation = Quat::from_rotation_z(enemy.rotation);
+        let mut bundle = EnemyBundle::from_animation_transform_size(
+            &animations.movement,
+            enemy_xf,
+            None,
+        );
+        bundle.health.current = enemy.health;
+        commands.spawn_bundle(bundle);
+        cfg.add_spawned();
+    }
+
+    commands.remove_resource::<PendingRestore>();
+    info!("Restored saved game");
+}
+
+/// The state of the run in progress, without writing it to disk
+///
+/// `None` if there is no game running (with a player).
+pub fn capture_savegame(world: &mut World) -> Option<SaveGame> {
+    let mut ss = SystemState::<(
+        Res<GameMode>,
+        Res<GameTimer>,
+        Res<UsedPickups>,
+        Res<DestroyedBarrels>,
+        SavedPlayerQuery,
+        SavedEnemyQuery,
+    )>::new(world);
+    let (mode, timer, used, destroyed, q_player, q_enemy) = ss.get(world);
+    build_savegame(*mode, &timer, &used, &destroyed, &q_player, &q_enemy)
+}
+
+/// Put the run in progress back into a state taken with `capture_savegame`
+///
+/// Enemies are replaced with the saved ones. Unlike continuing a saved game,
+/// this happens immediately.
+pub fn restore_savegame(world: &mut World, save: SaveGame) {
+    let enemies: Vec<Entity> = world
+        .query_filtered::<Entity, With<Enemy>>()
+        .iter(world)
+        .collect();
+    for e in enemies {
+        if let Some(emut) = world.get_entity_mut(e) {
+            emut.despawn_recursive();
+        }
+    }
+    world.get_resource_mut::<EnemyConfig>().unwrap().forget_spawned();
+
+    world.insert_resource(UsedPickups::from_saved(&save.used_pickups));
+    world.insert_resource(DestroyedBarrels::from_saved(&save.destroyed_barrels));
+
+    world.insert_resource(PendingRestore(save));
+    let mut system = IntoSystem::into_system(apply_pending_restore);
+    system.initialize(world);
+    system.run((), world);
+    system.apply_buffers(world);
+}
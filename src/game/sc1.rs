@@ -6,8 +6,9 @@ use iyes_bevy_util::BevyState;
 use crate::game::GameAssets;
 
 use crate::game::timer::GameTimer;
+use crate::scene_exporter::SaveScenePath;
 
-use super::GameCleanup;
+use super::{spawn_scenario_scene, GameCleanup};
 
 /// This plugin should add all Scenario1 specific stuff
 pub struct Scenario1Plugin<S: BevyState + Copy> {
@@ -27,7 +28,8 @@ impl<S: BevyState + Copy> Plugin for Scenario1Plugin<S> {
         // add systems to `self.state`
         app.add_system_set(
             SystemSet::on_enter(self.state)
-                .with_system(spawn_dynamic_scene)
+                .with_system(spawn_dynamic_scene.exclusive_system())
+                .with_system(set_save_path)
                 .with_system(init_game_timer)
                 .with_system(load_game_map)
         );
@@ -55,12 +57,22 @@ fn init_game_timer(
     commands.insert_resource(GameTimer(timer));
 }
 
-fn spawn_dynamic_scene(
-    mut scene_spawner: ResMut<SceneSpawner>,
+fn spawn_dynamic_scene(world: &mut World) {
+    let scene = world.get_resource::<Sc1Assets>().unwrap().scene.clone();
+    spawn_scenario_scene(world, &scene);
+}
+
+/// Make the editor save back to the file the scenario was loaded from
+fn set_save_path(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
     assets: Res<Sc1Assets>,
 ) {
-    scene_spawner.spawn_dynamic(assets.scene.clone());
+    if let Some(path) = asset_server.get_handle_path(&assets.scene) {
+        commands.insert_resource(SaveScenePath(path.path().to_owned()));
+    }
 }
+
 fn load_game_map(
     mut commands: Commands,
     assets: Res<Sc1Assets>,
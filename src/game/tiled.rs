@@ -0,0 +1,1184 @@
+//! Import levels made with the Tiled map editor
+//!
+//! Maps can be `.tmx` (XML) or `.tmj` (JSON) files; the loader does not claim
+//! `.json`, which other assets use. Tilesets can be embedded in the map or
+//! external (`.tsx`/`.tsj`/`.json`), and must use a single image.
+//! Only orthogonal, finite maps are supported; tile layer data must be CSV
+//! encoded (Tiled's default), not base64.
+//!
+//! The dev playground spawns `assets/maps/dev.tmx`, if there is one. To use
+//! a map in another level, spawn a `TiledLevelBundle` with its handle when
+//! entering the scenario's state, next to (or instead of) its scene:
+//!
+//! ```ignore
+//! fn load_tiled_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+//!     commands.spawn_bundle(TiledLevelBundle::new(asset_server.load("maps/level.tmx")));
+//! }
+//! ```
+//!
+//! Once the map has loaded, its contents are spawned as children:
+//!
+//! - every tile layer becomes sprites, stacked in layer order
+//! - every object in an object layer whose type (class) is the `EDITOR_ID` of
+//!   a blueprint becomes that blueprint, and the usual `init_bp_*` systems
+//!   take it from there. Rectangles become colliders of the same size.
+//!   Walls, hurt zones and sensors can also be circles (ellipses with equal
+//!   width and height) or convex polygons; other shapes are skipped, with a
+//!   warning.
+//!   Blueprint fields (like `damage` for `HurtZone`) can be set with custom
+//!   properties of the same name.
+//!
+//! Entities spawned from the map are not saved with the editor scene.
+//! They are respawned whenever the map file changes.
+
+use std::path::{Component as PathComponent, Path, PathBuf};
+use std::str::FromStr;
+
+use bevy::asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::{BoxedFuture, HashMap, HashSet};
+use serde::Deserialize;
+
+use crate::editor::collider::{EditableCircle, EditableCollider, EditablePolygon};
+use crate::scene_exporter::ExcludeFromScene;
+
+use super::blueprints::*;
+use super::collider::{self, HurtZone};
+use super::GameCleanup;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+const GID_MASK: u32 = !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY);
+
+/// Z of the first tile layer; each further layer goes slightly above
+const TILE_LAYER_Z: f32 = -1.5;
+const TILE_LAYER_Z_STEP: f32 = 0.01;
+
+#[derive(TypeUuid)]
+#[uuid = "5a1b4b2e-2f0e-4d4c-9a5e-7c0f3b8e6d21"]
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: Vec2,
+    pub tilesets: Vec<TiledTileset>,
+    pub layers: Vec<TiledLayer>,
+}
+
+pub struct TiledTileset {
+    pub first_gid: u32,
+    pub tile_count: u32,
+    pub tile_size: Vec2,
+    pub atlas: Handle<TextureAtlas>,
+}
+
+pub enum TiledLayer {
+    Tiles {
+        name: String,
+        width: u32,
+        height: u32,
+        offset: Vec2,
+        opacity: f32,
+        /// Global tile ids, row by row, including the flip flags
+        data: Vec<u32>,
+    },
+    Objects {
+        name: String,
+        offset: Vec2,
+        objects: Vec<TiledObject>,
+    },
+}
+
+pub struct TiledObject {
+    pub name: String,
+    /// "type" (or "class", in newer versions of Tiled)
+    pub kind: String,
+    /// Position, in Tiled's coordinates (y down)
+    pub position: Vec2,
+    pub size: Vec2,
+    /// Degrees, clockwise
+    pub rotation: f32,
+    /// Tile objects are anchored at the bottom left, instead of top left
+    pub is_tile: bool,
+    pub shape: TiledShape,
+    pub properties: HashMap<String, String>,
+}
+
+pub enum TiledShape {
+    Rectangle,
+    Ellipse,
+    /// Points relative to the position of the object, in Tiled's coordinates
+    Polygon(Vec<Vec2>),
+    /// Points, polylines and text, which nothing can be made from
+    Unsupported(&'static str),
+}
+
+impl TiledShape {
+    fn name(&self) -> &'static str {
+        match self {
+            TiledShape::Rectangle => "rectangle",
+            TiledShape::Ellipse => "ellipse",
+            TiledShape::Polygon(_) => "polygon",
+            TiledShape::Unsupported(name) => *name,
+        }
+    }
+}
+
+impl TiledObject {
+    fn property<T: FromStr>(&self, name: &str) -> Option<T> {
+        let value = self.properties.get(name)?;
+        match value.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                warn!("Tiled object {:?}: bad value for property {:?}: {:?}", self.name, name, value);
+                None
+            }
+        }
+    }
+
+    /// Transform of the center of the object, in bevy's coordinates
+    fn transform(&self, layer_offset: Vec2, z: f32) -> Transform {
+        let angle = self.rotation.to_radians();
+        let half = if self.is_tile {
+            Vec2::new(self.size.x, -self.size.y) / 2.0
+        } else {
+            self.size / 2.0
+        };
+        // objects rotate around their anchor
+        let (sin, cos) = angle.sin_cos();
+        let center = self.position + layer_offset + Vec2::new(
+            half.x * cos - half.y * sin,
+            half.x * sin + half.y * cos,
+        );
+        Transform {
+            translation: Vec3::new(center.x, -center.y, z),
+            rotation: Quat::from_rotation_z(-angle),
+            ..Default::default()
+        }
+    }
+}
+
+/// Add to an entity to spawn the contents of a Tiled map as its children
+#[derive(Component)]
+pub struct TiledLevel(pub Handle<TiledMap>);
+
+/// The contents of the map have been spawned
+#[derive(Component)]
+pub struct TiledLevelSpawned;
+
+#[derive(Bundle)]
+pub struct TiledLevelBundle {
+    pub level: TiledLevel,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub cleanup: GameCleanup,
+}
+
+impl TiledLevelBundle {
+    pub fn new(map: Handle<TiledMap>) -> Self {
+        TiledLevelBundle {
+            level: TiledLevel(map),
+            transform: Transform::default(),
+            global_transform: GlobalTransform::default(),
+            cleanup: GameCleanup,
+        }
+    }
+}
+
+pub struct TiledPlugin;
+
+impl Plugin for TiledPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<TiledMap>();
+        app.init_asset_loader::<TiledMapLoader>();
+        app.add_system(spawn_tiled_levels);
+    }
+}
+
+fn spawn_tiled_levels(
+    mut commands: Commands,
+    maps: Res<Assets<TiledMap>>,
+    mut evr: EventReader<AssetEvent<TiledMap>>,
+    q_level: Query<(Entity, &TiledLevel, Option<&TiledLevelSpawned>)>,
+) {
+    let modified: HashSet<Handle<TiledMap>> = evr.iter()
+        .filter_map(|ev| match ev {
+            AssetEvent::Modified { handle } => Some(handle.clone()),
+            _ => None,
+        })
+        .collect();
+
+    for (e, level, spawned) in q_level.iter() {
+        if spawned.is_some() {
+            if !modified.contains(&level.0) {
+                continue;
+            }
+            commands.entity(e).despawn_descendants();
+        }
+        let map = if let Some(map) = maps.get(&level.0) {
+            map
+        } else {
+            continue;
+        };
+        commands.entity(e)
+            .insert(TiledLevelSpawned)
+            .with_children(|p| spawn_map_contents(p, map));
+    }
+}
+
+fn spawn_map_contents(p: &mut ChildBuilder, map: &TiledMap) {
+    let mut z = TILE_LAYER_Z;
+    for layer in map.layers.iter() {
+        match layer {
+            TiledLayer::Tiles { width, height, offset, opacity, data, .. } => {
+                for (i, gid) in data.iter().enumerate() {
+                    let col = i as u32 % width;
+                    let row = i as u32 / width;
+                    if row >= *height {
+                        break;
+                    }
+                    spawn_tile(p, map, *gid, col, row, *offset, *opacity, z);
+                }
+                z += TILE_LAYER_Z_STEP;
+            }
+            TiledLayer::Objects { name, offset, objects } => {
+                for obj in objects.iter() {
+                    if !spawn_object(p, obj, *offset) {
+                        warn!(
+                            "Tiled layer {:?}: object {:?} has unknown type {:?}",
+                            name, obj.name, obj.kind
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_tile(
+    p: &mut ChildBuilder,
+    map: &TiledMap,
+    gid: u32,
+    col: u32,
+    row: u32,
+    offset: Vec2,
+    opacity: f32,
+    z: f32,
+) {
+    let id = gid & GID_MASK;
+    if id == 0 {
+        return;
+    }
+    let tileset = if let Some(ts) = map.tilesets.iter()
+        .rev()
+        .find(|ts| ts.first_gid <= id)
+    {
+        ts
+    } else {
+        return;
+    };
+    let index = (id - tileset.first_gid) as usize;
+    if index as u32 >= tileset.tile_count {
+        return;
+    }
+
+    // tiles larger than the grid stick out of the top of their cell
+    let x = col as f32 * map.tile_size.x + tileset.tile_size.x / 2.0 + offset.x;
+    let y = (row + 1) as f32 * map.tile_size.y - tileset.tile_size.y / 2.0 + offset.y;
+
+    // tiled flips diagonally first, then horizontally/vertically;
+    // a diagonal flip is a horizontal flip plus a 90 degree rotation
+    let flip_h = gid & FLIPPED_HORIZONTALLY != 0;
+    let flip_v = gid & FLIPPED_VERTICALLY != 0;
+    let (flip_x, flip_y, rotation) = if gid & FLIPPED_DIAGONALLY != 0 {
+        (!flip_v, flip_h, Quat::from_rotation_z(std::f32::consts::FRAC_PI_2))
+    } else {
+        (flip_h, flip_v, Quat::IDENTITY)
+    };
+
+    p.spawn_bundle(SpriteSheetBundle {
+        sprite: TextureAtlasSprite {
+            index,
+            flip_x,
+            flip_y,
+            color: Color::rgba(1.0, 1.0, 1.0, opacity),
+            ..Default::default()
+        },
+        texture_atlas: tileset.atlas.clone(),
+        transform: Transform {
+            translation: Vec3::new(x, -y, z),
+            rotation,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+}
+
+/// Returns false if the object type is not a known blueprint
+fn spawn_object(p: &mut ChildBuilder, obj: &TiledObject, offset: Vec2) -> bool {
+    let kind = if obj.kind.is_empty() { &obj.name } else { &obj.kind };
+    match kind.as_str() {
+        "Wall" => spawn_collider(p, obj, offset, collider::Wall),
+        "HurtZone" => {
+            let default = HurtZone::default();
+            let hz = HurtZone {
+                interval_secs: obj.property("interval_secs").unwrap_or(default.interval_secs),
+                damage: obj.property("damage").unwrap_or(default.damage),
+            };
+            spawn_collider(p, obj, offset, hz);
+        }
+        "WinZone" => spawn_collider(p, obj, offset, collider::WinZone),
+        "SpawnZone" => spawn_collider(p, obj, offset, collider::SpawnZone),
+        "Medkit" => {
+            let mut bundle = MedkitBlueprintBundle::default();
+            bundle.transform = obj.transform(offset, Medkit::DEFAULT_Z);
+            if let Some(healing) = obj.property("healing") {
+                bundle.medkit.healing = healing;
+            }
+            p.spawn_bundle(bundle).insert(ExcludeFromScene);
+        }
+        "AmmoBox" => {
+            let mut bundle = AmmoBoxBlueprintBundle::default();
+            bundle.transform = obj.transform(offset, AmmoBox::DEFAULT_Z);
+            if let Some(amount) = obj.property("amount") {
+                bundle.ammo_box.amount = amount;
+            }
+            p.spawn_bundle(bundle).insert(ExcludeFromScene);
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn spawn_collider<T: ColliderBehavior>(
+    p: &mut ChildBuilder,
+    obj: &TiledObject,
+    offset: Vec2,
+    marker: T,
+) {
+    let shaped = T::KINDENUM.allows_shapes();
+    let skip = |why: &str| warn!(
+        "Tiled object {:?}: skipped, {} {}",
+        obj.name, obj.shape.name(), why
+    );
+    let mut cmd = match &obj.shape {
+        TiledShape::Rectangle => {
+            let mut cmd = p.spawn();
+            cmd.insert(EditableCollider {
+                half_extends: obj.size / 2.0,
+            });
+            cmd
+        }
+        TiledShape::Ellipse if shaped => {
+            if (obj.size.x - obj.size.y).abs() > 0.5 {
+                skip("is not a circle");
+                return;
+            }
+            let mut cmd = p.spawn();
+            cmd.insert(EditableCircle {
+                radius: obj.size.x / 2.0,
+            });
+            cmd
+        }
+        TiledShape::Polygon(points) if shaped => {
+            let points: Vec<Vec2> = points.iter()
+                .map(|p| Vec2::new(p.x, -p.y))
+                .collect();
+            if !collider::ColliderGeometry::is_convex(&points) {
+                skip("is not convex");
+                return;
+            }
+            let mut cmd = p.spawn();
+            cmd.insert(EditablePolygon { points });
+            cmd
+        }
+        TiledShape::Ellipse | TiledShape::Polygon(_) => {
+            skip(&format!("is not supported for {}", T::EDITOR_ID));
+            return;
+        }
+        TiledShape::Unsupported(_) => {
+            skip("is not supported");
+            return;
+        }
+    };
+    cmd.insert_bundle((
+        marker,
+        obj.transform(offset, T::DEFAULT_Z),
+        GlobalTransform::default(),
+        ExcludeFromScene,
+    ));
+}
+
+// LOADING
+
+/// Intermediate representation of a tileset, before its image is loaded
+struct TilesetData {
+    tile_size: Vec2,
+    tile_count: u32,
+    columns: u32,
+    spacing: f32,
+    /// Relative to the file the tileset was defined in
+    image: String,
+}
+
+enum TilesetSource {
+    Embedded(TilesetData),
+    /// Path relative to the map file
+    External(String),
+}
+
+struct MapData {
+    width: u32,
+    height: u32,
+    tile_size: Vec2,
+    tilesets: Vec<(u32, TilesetSource)>,
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Default)]
+pub struct TiledMapLoader;
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let text = std::str::from_utf8(bytes)?;
+            let map_path = load_context.path().to_owned();
+            let map = if is_xml(&map_path) {
+                parse_map_xml(text)?
+            } else {
+                parse_map_json(text)?
+            };
+            let map_dir = map_path.parent().unwrap_or_else(|| Path::new(""));
+
+            let mut tilesets = Vec::new();
+            for (i, (first_gid, source)) in map.tilesets.into_iter().enumerate() {
+                let (data, dir) = match source {
+                    TilesetSource::Embedded(data) => (data, map_dir.to_owned()),
+                    TilesetSource::External(file) => {
+                        let path = normalize(&map_dir.join(file));
+                        let bytes = load_context.read_asset_bytes(&path).await?;
+                        let text = std::str::from_utf8(&bytes)?;
+                        let data = if is_xml(&path) {
+                            parse_tileset_xml(text)?
+                        } else {
+                            parse_tileset_json(text)?
+                        };
+                        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+                        (data, dir)
+                    }
+                };
+
+                let image_path = AssetPath::new(normalize(&dir.join(&data.image)), None);
+                let texture = load_context.get_handle(image_path.clone());
+                let columns = data.columns.max(1);
+                let rows = (data.tile_count + columns - 1) / columns;
+                let atlas = TextureAtlas::from_grid_with_padding(
+                    texture,
+                    data.tile_size,
+                    columns as usize,
+                    rows as usize,
+                    Vec2::splat(data.spacing),
+                );
+                let atlas = load_context.set_labeled_asset(
+                    &format!("tileset{}", i),
+                    LoadedAsset::new(atlas).with_dependency(image_path),
+                );
+                tilesets.push(TiledTileset {
+                    first_gid,
+                    tile_count: data.tile_count,
+                    tile_size: data.tile_size,
+                    atlas,
+                });
+            }
+
+            load_context.set_default_asset(LoadedAsset::new(TiledMap {
+                width: map.width,
+                height: map.height,
+                tile_size: map.tile_size,
+                tilesets,
+                layers: map.layers,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx", "tmj"]
+    }
+}
+
+fn is_xml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("tmx") | Some("tsx")
+    )
+}
+
+/// Resolve `..` in paths, which the asset server does not understand
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            PathComponent::ParentDir => {
+                out.pop();
+            }
+            PathComponent::CurDir => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_csv(text: &str) -> Result<Vec<u32>, anyhow::Error> {
+    text.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u32>().map_err(anyhow::Error::from))
+        .collect()
+}
+
+// XML (.tmx/.tsx)
+
+fn attr<T: FromStr>(node: roxmltree::Node, name: &str) -> Result<T, anyhow::Error> {
+    let value = node.attribute(name)
+        .ok_or_else(|| anyhow::anyhow!("<{}> is missing attribute {:?}", node.tag_name().name(), name))?;
+    value.parse()
+        .map_err(|_| anyhow::anyhow!("<{}> has bad value for {:?}: {:?}", node.tag_name().name(), name, value))
+}
+
+fn attr_or<T: FromStr>(node: roxmltree::Node, name: &str, default: T) -> Result<T, anyhow::Error> {
+    if node.has_attribute(name) {
+        attr(node, name)
+    } else {
+        Ok(default)
+    }
+}
+
+fn parse_map_xml(text: &str) -> Result<MapData, anyhow::Error> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    check_map_support(
+        root.attribute("orientation").unwrap_or("orthogonal"),
+        root.attribute("infinite") == Some("1"),
+    )?;
+
+    let mut tilesets = Vec::new();
+    for node in root.children().filter(|n| n.has_tag_name("tileset")) {
+        let first_gid = attr(node, "firstgid")?;
+        let source = if let Some(file) = node.attribute("source") {
+            TilesetSource::External(file.to_owned())
+        } else {
+            TilesetSource::Embedded(read_tileset_xml(node)?)
+        };
+        tilesets.push((first_gid, source));
+    }
+
+    let mut layers = Vec::new();
+    read_layers_xml(root, Vec2::ZERO, &mut layers)?;
+
+    Ok(MapData {
+        width: attr(root, "width")?,
+        height: attr(root, "height")?,
+        tile_size: Vec2::new(attr(root, "tilewidth")?, attr(root, "tileheight")?),
+        tilesets,
+        layers,
+    })
+}
+
+/// Collect layers in order, flattening groups
+fn read_layers_xml(
+    parent: roxmltree::Node,
+    parent_offset: Vec2,
+    layers: &mut Vec<TiledLayer>,
+) -> Result<(), anyhow::Error> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        if attr_or(node, "visible", 1u32)? == 0 {
+            continue;
+        }
+        let name = node.attribute("name").unwrap_or_default().to_owned();
+        let offset = parent_offset + Vec2::new(
+            attr_or(node, "offsetx", 0.0)?,
+            attr_or(node, "offsety", 0.0)?,
+        );
+        match node.tag_name().name() {
+            "layer" => {
+                let data = node.children()
+                    .find(|n| n.has_tag_name("data"))
+                    .ok_or_else(|| anyhow::anyhow!("layer {:?} has no data", name))?;
+                let data = match data.attribute("encoding") {
+                    Some("csv") => parse_csv(data.text().unwrap_or_default())?,
+                    None => data.children()
+                        .filter(|n| n.has_tag_name("tile"))
+                        .map(|n| attr_or(n, "gid", 0u32))
+                        .collect::<Result<_, _>>()?,
+                    Some(other) => anyhow::bail!(
+                        "layer {:?}: {:?} encoding is not supported, save the map with CSV",
+                        name, other
+                    ),
+                };
+                layers.push(TiledLayer::Tiles {
+                    name,
+                    width: attr(node, "width")?,
+                    height: attr(node, "height")?,
+                    offset,
+                    opacity: attr_or(node, "opacity", 1.0)?,
+                    data,
+                });
+            }
+            "objectgroup" => {
+                let objects = node.children()
+                    .filter(|n| n.has_tag_name("object"))
+                    .map(read_object_xml)
+                    .collect::<Result<_, _>>()?;
+                layers.push(TiledLayer::Objects { name, offset, objects });
+            }
+            "group" => read_layers_xml(node, offset, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_object_xml(node: roxmltree::Node) -> Result<TiledObject, anyhow::Error> {
+    let mut properties = HashMap::default();
+    if let Some(props) = node.children().find(|n| n.has_tag_name("properties")) {
+        for prop in props.children().filter(|n| n.has_tag_name("property")) {
+            let value = prop.attribute("value")
+                .or_else(|| prop.text())
+                .unwrap_or_default();
+            properties.insert(attr::<String>(prop, "name")?, value.to_owned());
+        }
+    }
+    Ok(TiledObject {
+        name: node.attribute("name").unwrap_or_default().to_owned(),
+        kind: node.attribute("type")
+            .or_else(|| node.attribute("class"))
+            .unwrap_or_default()
+            .to_owned(),
+        position: Vec2::new(attr(node, "x")?, attr(node, "y")?),
+        size: Vec2::new(attr_or(node, "width", 0.0)?, attr_or(node, "height", 0.0)?),
+        rotation: attr_or(node, "rotation", 0.0)?,
+        is_tile: node.has_attribute("gid"),
+        shape: read_shape_xml(node)?,
+        properties,
+    })
+}
+
+fn read_shape_xml(node: roxmltree::Node) -> Result<TiledShape, anyhow::Error> {
+    for child in node.children().filter(|n| n.is_element()) {
+        match child.tag_name().name() {
+            "ellipse" => return Ok(TiledShape::Ellipse),
+            "polygon" => {
+                // "x1,y1 x2,y2 ..."
+                let points = attr::<String>(child, "points")?
+                    .split_whitespace()
+                    .map(|pair| {
+                        let (x, y) = pair.split_once(',')?;
+                        Some(Vec2::new(x.parse().ok()?, y.parse().ok()?))
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| anyhow::anyhow!("bad polygon points"))?;
+                return Ok(TiledShape::Polygon(points));
+            }
+            "point" => return Ok(TiledShape::Unsupported("point")),
+            "polyline" => return Ok(TiledShape::Unsupported("polyline")),
+            "text" => return Ok(TiledShape::Unsupported("text")),
+            _ => {}
+        }
+    }
+    Ok(TiledShape::Rectangle)
+}
+
+fn parse_tileset_xml(text: &str) -> Result<TilesetData, anyhow::Error> {
+    let doc = roxmltree::Document::parse(text)?;
+    read_tileset_xml(doc.root_element())
+}
+
+fn read_tileset_xml(node: roxmltree::Node) -> Result<TilesetData, anyhow::Error> {
+    let image = node.children()
+        .find(|n| n.has_tag_name("image"))
+        .ok_or_else(|| anyhow::anyhow!("only tilesets based on a single image are supported"))?;
+    Ok(TilesetData {
+        tile_size: Vec2::new(attr(node, "tilewidth")?, attr(node, "tileheight")?),
+        tile_count: attr(node, "tilecount")?,
+        columns: attr(node, "columns")?,
+        spacing: attr_or(node, "spacing", 0.0)?,
+        image: attr(image, "source")?,
+    })
+}
+
+// JSON (.tmj/.tsj/.json)
+
+#[derive(Deserialize)]
+struct JsonMap {
+    #[serde(default = "default_orientation")]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    width: u32,
+    height: u32,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    tilesets: Vec<JsonMapTileset>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+fn default_orientation() -> String {
+    "orthogonal".into()
+}
+
+#[derive(Deserialize)]
+struct JsonMapTileset {
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(flatten)]
+    embedded: Option<JsonTileset>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    tilewidth: f32,
+    tileheight: f32,
+    tilecount: u32,
+    columns: u32,
+    #[serde(default)]
+    spacing: f32,
+    image: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "default_true")]
+    visible: bool,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    point: bool,
+    polygon: Option<Vec<JsonPoint>>,
+    polyline: Option<serde_json::Value>,
+    text: Option<serde_json::Value>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+fn parse_map_json(text: &str) -> Result<MapData, anyhow::Error> {
+    let map: JsonMap = serde_json::from_str(text)?;
+    check_map_support(&map.orientation, map.infinite)?;
+
+    let mut tilesets = Vec::new();
+    for ts in map.tilesets {
+        let source = match (ts.source, ts.embedded) {
+            (Some(file), _) => TilesetSource::External(file),
+            (None, Some(embedded)) => TilesetSource::Embedded(tileset_from_json(embedded)?),
+            (None, None) => anyhow::bail!("tileset {} has no data", ts.firstgid),
+        };
+        tilesets.push((ts.firstgid, source));
+    }
+
+    let mut layers = Vec::new();
+    read_layers_json(map.layers, Vec2::ZERO, &mut layers)?;
+
+    Ok(MapData {
+        width: map.width,
+        height: map.height,
+        tile_size: Vec2::new(map.tilewidth, map.tileheight),
+        tilesets,
+        layers,
+    })
+}
+
+fn read_layers_json(
+    json: Vec<JsonLayer>,
+    parent_offset: Vec2,
+    layers: &mut Vec<TiledLayer>,
+) -> Result<(), anyhow::Error> {
+    for layer in json {
+        if !layer.visible {
+            continue;
+        }
+        let offset = parent_offset + Vec2::new(layer.offsetx, layer.offsety);
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let data = match layer.data {
+                    Some(serde_json::Value::Array(data)) => data.iter()
+                        .map(|v| v.as_u64().map(|v| v as u32))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| anyhow::anyhow!("layer {:?} has bad tile data", layer.name))?,
+                    _ => anyhow::bail!(
+                        "layer {:?}: only CSV tile layer format is supported",
+                        layer.name
+                    ),
+                };
+                layers.push(TiledLayer::Tiles {
+                    name: layer.name,
+                    width: layer.width,
+                    height: layer.height,
+                    offset,
+                    opacity: layer.opacity,
+                    data,
+                });
+            }
+            "objectgroup" => {
+                let objects = layer.objects.into_iter().map(object_from_json).collect();
+                layers.push(TiledLayer::Objects {
+                    name: layer.name,
+                    offset,
+                    objects,
+                });
+            }
+            "group" => read_layers_json(layer.layers, offset, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn object_from_json(obj: JsonObject) -> TiledObject {
+    let properties = obj.properties.into_iter()
+        .map(|p| {
+            let value = match p.value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            (p.name, value)
+        })
+        .collect();
+    let shape = if let Some(points) = obj.polygon {
+        TiledShape::Polygon(points.iter().map(|p| Vec2::new(p.x, p.y)).collect())
+    } else if obj.ellipse {
+        TiledShape::Ellipse
+    } else if obj.point {
+        TiledShape::Unsupported("point")
+    } else if obj.polyline.is_some() {
+        TiledShape::Unsupported("polyline")
+    } else if obj.text.is_some() {
+        TiledShape::Unsupported("text")
+    } else {
+        TiledShape::Rectangle
+    };
+    TiledObject {
+        name: obj.name,
+        kind: if obj.kind.is_empty() { obj.class } else { obj.kind },
+        position: Vec2::new(obj.x, obj.y),
+        size: Vec2::new(obj.width, obj.height),
+        rotation: obj.rotation,
+        is_tile: obj.gid.is_some(),
+        shape,
+        properties,
+    }
+}
+
+fn parse_tileset_json(text: &str) -> Result<TilesetData, anyhow::Error> {
+    tileset_from_json(serde_json::from_str(text)?)
+}
+
+fn tileset_from_json(ts: JsonTileset) -> Result<TilesetData, anyhow::Error> {
+    Ok(TilesetData {
+        tile_size: Vec2::new(ts.tilewidth, ts.tileheight),
+        tile_count: ts.tilecount,
+        columns: ts.columns,
+        spacing: ts.spacing,
+        image: ts.image
+            .ok_or_else(|| anyhow::anyhow!("only tilesets based on a single image are supported"))?,
+    })
+}
+
+fn check_map_support(orientation: &str, infinite: bool) -> Result<(), anyhow::Error> {
+    if orientation != "orthogonal" {
+        anyhow::bail!("{} maps are not supported, only orthogonal", orientation);
+    }
+    if infinite {
+        anyhow::bail!("infinite maps are not supported");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.8" orientation="orthogonal" renderorder="right-down" width="2" height="2" tilewidth="32" tileheight="32" infinite="0">
+ <tileset firstgid="1" name="walls" tilewidth="32" tileheight="32" spacing="2" tilecount="4" columns="2">
+  <image source="walls.png" width="66" height="66"/>
+ </tileset>
+ <tileset firstgid="5" source="../tilesets/props.tsx"/>
+ <layer id="1" name="floor" width="2" height="2" opacity="0.5">
+  <data encoding="csv">
+1,2,
+0,2147483651
+</data>
+ </layer>
+ <group id="2" name="outer" offsetx="10" offsety="20">
+  <group id="3" name="inner" offsetx="1" offsety="2">
+   <objectgroup id="4" name="things" offsetx="100" offsety="200">
+    <object id="1" name="wall" type="Wall" x="0" y="0" width="64" height="32"/>
+    <object id="2" name="pit" class="HurtZone" x="10" y="10" width="40" height="40">
+     <properties>
+      <property name="damage" type="float" value="5"/>
+     </properties>
+     <ellipse/>
+    </object>
+    <object id="3" name="ramp" type="Wall" x="5" y="6">
+     <polygon points="0,0 32,0 0,-32"/>
+    </object>
+   </objectgroup>
+  </group>
+ </group>
+</map>
+"#;
+
+    const TILESET_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.8" name="props" tilewidth="16" tileheight="24" tilecount="6" columns="3">
+ <image source="../images/props.png" width="48" height="48"/>
+</tileset>
+"#;
+
+    const MAP_JSON: &str = r#"{
+  "orientation": "orthogonal",
+  "infinite": false,
+  "width": 2,
+  "height": 2,
+  "tilewidth": 32,
+  "tileheight": 32,
+  "tilesets": [
+    { "firstgid": 1, "tilewidth": 32, "tileheight": 32, "spacing": 2, "tilecount": 4, "columns": 2, "image": "walls.png" },
+    { "firstgid": 5, "source": "../tilesets/props.tsj" }
+  ],
+  "layers": [
+    { "type": "tilelayer", "name": "floor", "width": 2, "height": 2, "opacity": 0.5, "data": [1, 2, 0, 2147483651] },
+    { "type": "group", "name": "outer", "offsetx": 10, "offsety": 20, "layers": [
+      { "type": "group", "name": "inner", "offsetx": 1, "offsety": 2, "layers": [
+        { "type": "objectgroup", "name": "things", "offsetx": 100, "offsety": 200, "objects": [
+          { "name": "wall", "type": "Wall", "x": 0, "y": 0, "width": 64, "height": 32 },
+          { "name": "pit", "class": "HurtZone", "x": 10, "y": 10, "width": 40, "height": 40, "ellipse": true,
+            "properties": [ { "name": "damage", "type": "float", "value": 5 } ] },
+          { "name": "ramp", "type": "Wall", "x": 5, "y": 6,
+            "polygon": [ { "x": 0, "y": 0 }, { "x": 32, "y": 0 }, { "x": 0, "y": -32 } ] }
+        ] }
+      ] }
+    ] }
+  ]
+}"#;
+
+    const TILESET_JSON: &str = r#"{
+  "name": "props",
+  "tilewidth": 16,
+  "tileheight": 24,
+  "tilecount": 6,
+  "columns": 3,
+  "image": "../images/props.png"
+}"#;
+
+    /// Both formats describe the same map
+    fn check_map(map: &MapData, external_tileset: &str) {
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.tile_size, Vec2::new(32.0, 32.0));
+
+        assert_eq!(map.tilesets.len(), 2);
+        match &map.tilesets[0] {
+            (1, TilesetSource::Embedded(data)) => {
+                assert_eq!(data.tile_size, Vec2::new(32.0, 32.0));
+                assert_eq!((data.tile_count, data.columns), (4, 2));
+                assert_eq!(data.spacing, 2.0);
+                assert_eq!(data.image, "walls.png");
+            }
+            _ => panic!("first tileset should be embedded, with first gid 1"),
+        }
+        match &map.tilesets[1] {
+            (5, TilesetSource::External(file)) => assert_eq!(file, external_tileset),
+            _ => panic!("second tileset should be external, with first gid 5"),
+        }
+
+        assert_eq!(map.layers.len(), 2);
+        match &map.layers[0] {
+            TiledLayer::Tiles { name, width, height, offset, opacity, data } => {
+                assert_eq!(name, "floor");
+                assert_eq!((*width, *height), (2, 2));
+                assert_eq!(*offset, Vec2::ZERO);
+                assert_eq!(*opacity, 0.5);
+                assert_eq!(data, &[1, 2, 0, FLIPPED_HORIZONTALLY | 3]);
+            }
+            _ => panic!("first layer should be tiles"),
+        }
+        let (offset, objects) = match &map.layers[1] {
+            TiledLayer::Objects { name, offset, objects } => {
+                assert_eq!(name, "things");
+                (*offset, objects)
+            }
+            _ => panic!("second layer should be objects"),
+        };
+        // the offsets of both groups and the layer itself
+        assert_eq!(offset, Vec2::new(111.0, 222.0));
+
+        assert_eq!(objects.len(), 3);
+        let wall = &objects[0];
+        assert_eq!((wall.name.as_str(), wall.kind.as_str()), ("wall", "Wall"));
+        assert_eq!(wall.size, Vec2::new(64.0, 32.0));
+        assert!(matches!(wall.shape, TiledShape::Rectangle));
+
+        let pit = &objects[1];
+        assert_eq!(pit.kind, "HurtZone");
+        assert_eq!(pit.position, Vec2::new(10.0, 10.0));
+        assert!(matches!(pit.shape, TiledShape::Ellipse));
+        assert_eq!(pit.property::<f32>("damage"), Some(5.0));
+
+        let ramp = &objects[2];
+        assert_eq!(ramp.size, Vec2::ZERO);
+        match &ramp.shape {
+            TiledShape::Polygon(points) => assert_eq!(points, &[
+                Vec2::new(0.0, 0.0),
+                Vec2::new(32.0, 0.0),
+                Vec2::new(0.0, -32.0),
+            ]),
+            _ => panic!("ramp should be a polygon"),
+        }
+    }
+
+    fn check_tileset(data: &TilesetData) {
+        assert_eq!(data.tile_size, Vec2::new(16.0, 24.0));
+        assert_eq!((data.tile_count, data.columns), (6, 3));
+        assert_eq!(data.spacing, 0.0);
+        assert_eq!(data.image, "../images/props.png");
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(parse_csv("1,2,\n3,\n0\n").unwrap(), vec![1, 2, 3, 0]);
+        assert_eq!(parse_csv("  \n").unwrap(), Vec::<u32>::new());
+        assert!(parse_csv("1,x,3").is_err());
+    }
+
+    #[test]
+    fn xml_map() {
+        check_map(&parse_map_xml(MAP_XML).unwrap(), "../tilesets/props.tsx");
+    }
+
+    #[test]
+    fn xml_tileset() {
+        check_tileset(&parse_tileset_xml(TILESET_XML).unwrap());
+    }
+
+    #[test]
+    fn json_map() {
+        check_map(&parse_map_json(MAP_JSON).unwrap(), "../tilesets/props.tsj");
+    }
+
+    #[test]
+    fn json_tileset() {
+        check_tileset(&parse_tileset_json(TILESET_JSON).unwrap());
+    }
+
+    #[test]
+    fn external_tileset_paths() {
+        let path = normalize(&Path::new("maps").join("../tilesets/props.tsx"));
+        assert_eq!(path, Path::new("tilesets/props.tsx"));
+        assert!(is_xml(&path));
+        assert!(!is_xml(Path::new("tilesets/props.tsj")));
+    }
+
+    #[test]
+    fn xml_unsupported_encoding() {
+        let map = MAP_XML.replace(
+            "<data encoding=\"csv\">\n1,2,\n0,2147483651\n</data>",
+            "<data encoding=\"base64\">AQAAAAIAAAA=</data>",
+        );
+        assert_ne!(map, MAP_XML);
+        let err = parse_map_xml(&map).err().unwrap();
+        assert!(err.to_string().contains("encoding"), "{}", err);
+    }
+
+    #[test]
+    fn json_unsupported_encoding() {
+        let map = MAP_JSON.replace(
+            "\"data\": [1, 2, 0, 2147483651]",
+            "\"encoding\": \"base64\", \"data\": \"AQAAAAIAAAA=\"",
+        );
+        assert_ne!(map, MAP_JSON);
+        let err = parse_map_json(&map).err().unwrap();
+        assert!(err.to_string().contains("CSV"), "{}", err);
+    }
+
+    #[test]
+    fn infinite_maps() {
+        let map = MAP_XML.replace("infinite=\"0\"", "infinite=\"1\"");
+        let err = parse_map_xml(&map).err().unwrap();
+        assert!(err.to_string().contains("infinite"), "{}", err);
+
+        let map = MAP_JSON.replace("\"infinite\": false", "\"infinite\": true");
+        let err = parse_map_json(&map).err().unwrap();
+        assert!(err.to_string().contains("infinite"), "{}", err);
+    }
+
+    #[test]
+    fn other_orientations() {
+        let map = MAP_XML.replace("orientation=\"orthogonal\"", "orientation=\"isometric\"");
+        assert!(parse_map_xml(&map).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+use enum_iterator::IntoEnumIterator;
+
+pub const GAMENAME: &str = "Absconder";
+
+pub mod editor;
+pub mod game;
+pub mod lint;
+pub mod ui;
+pub mod util;
+pub mod scene_exporter;
+
+/// Each level/map in the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+#[derive(IntoEnumIterator)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum GameMode {
+    Scenario1,
+    DevPlayground,
+}
+
+/// Application states
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub enum AppState {
+    MainAssetLoading,
+    MainMenu,
+    GameAssetLoading(GameMode),
+    InGame(GameMode),
+    GameOver,
+    Credits,
+    DevEditor,
+}
+
+/// Cant we have stageless already! :yeet:
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(StageLabel)]
+pub enum FuckStages {
+    Pre,
+    Post,
+}
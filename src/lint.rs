@@ -0,0 +1,341 @@
+//! Headless checks for scene files, used by the `bevyjam-lint` tool
+//!
+//! The scene is loaded into a bare `World`, with the same type registrations
+//! as the game, and then inspected with regular queries.
+
+use std::fmt;
+use std::path::Path;
+
+use bevy::ecs::entity::EntityMap;
+use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
+use bevy::utils::HashMap;
+
+use crate::editor::collider::{EditableCircle, EditableCollider, EditablePolygon};
+use crate::game::blueprints::{AmmoBox, BlueprintsPlugin, ExportableTypes, Medkit, PlayerStart};
+use crate::game::collider::{ColliderGeometry, SpawnZone, Wall, WinZone};
+use crate::scene_exporter;
+use crate::FuckStages;
+
+/// Walls may touch, but not overlap by more than this
+const WALL_OVERLAP_TOLERANCE: f32 = 1.0;
+
+pub struct LintProblem {
+    /// Entity id, as written in the scene file
+    pub entity: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for LintProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(e) = self.entity {
+            write!(f, "entity {}: {}", e, self.message)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+/// An app with everything registered that could appear in a scene,
+/// that is never run
+fn lint_app() -> App {
+    let mut app = App::new();
+    app.add_plugin(bevy::core::CorePlugin);
+    app.add_plugin(bevy::transform::TransformPlugin);
+    app.add_stage_after(CoreStage::Update, FuckStages::Post, SystemStage::parallel());
+    app.add_stage_before(CoreStage::Update, FuckStages::Pre, SystemStage::parallel());
+    app.add_plugin(BlueprintsPlugin);
+    app
+}
+
+/// Load a scene file and report everything wrong with it
+///
+/// Returns `Err` if the file could not be loaded at all.
+pub fn lint_scene_file(path: &Path) -> Result<Vec<LintProblem>, String> {
+    let mut app = lint_app();
+    let type_registry = app.world.get_resource::<TypeRegistry>().unwrap().clone();
+    let scene = scene_exporter::load_scene_file(&type_registry, path)?;
+    lint_scene(&mut app.world, &scene)
+}
+
+/// Spawn a scene into the world of a `lint_app` and report everything wrong with it
+fn lint_scene(world: &mut World, scene: &DynamicScene) -> Result<Vec<LintProblem>, String> {
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap().clone();
+
+    let mut problems = Vec::new();
+
+    // components the game would never export
+    {
+        let exportable = world.get_resource::<ExportableTypes>().unwrap();
+        let type_registry = type_registry.read();
+        for entity in scene.entities.iter() {
+            for component in entity.components.iter() {
+                let known = type_registry.get_with_name(component.type_name())
+                    .map(|r| exportable.names.contains(r.short_name()))
+                    .unwrap_or(false);
+                if !known {
+                    problems.push(LintProblem {
+                        entity: Some(entity.entity),
+                        message: format!("unknown component {}", component.type_name()),
+                    });
+                }
+            }
+        }
+    }
+
+    let mut entity_map = EntityMap::default();
+    scene.write_to_world(world, &mut entity_map)
+        .map_err(|e| format!("Could not spawn scene: {:?}", e))?;
+    let scene_ids: HashMap<Entity, u32> = entity_map.keys()
+        .map(|k| (entity_map.get(k).unwrap(), k.id()))
+        .collect();
+
+    let mut problem = |e: Option<Entity>, message: String| {
+        problems.push(LintProblem {
+            entity: e.and_then(|e| scene_ids.get(&e).copied()),
+            message,
+        });
+    };
+
+    if world.query_filtered::<(), With<WinZone>>().iter(world).next().is_none() {
+        problem(None, "no WinZone; the scenario can never be won".into());
+    }
+    if world.query_filtered::<(), With<SpawnZone>>().iter(world).next().is_none() {
+        problem(None, "no SpawnZone; no enemies will spawn".into());
+    }
+    if world.query_filtered::<(), With<PlayerStart>>().iter(world).next().is_none() {
+        problem(None, "no PlayerStart; the game will refuse to start".into());
+    }
+
+    // the game despawns these, assuming they were never placed
+    let pickups: Vec<Entity> = world
+        .query_filtered::<Entity, (Or<(With<Medkit>, With<AmmoBox>)>, Without<Parent>)>()
+        .iter(world)
+        .collect();
+    for e in pickups {
+        if world_transform(world, e).map(|xf| xf.translation) == Some(Vec3::ZERO) {
+            problem(Some(e), "pickup at the origin will be despawned".into());
+        }
+    }
+
+    for (e, edit) in world.query::<(Entity, &EditableCollider)>().iter(world) {
+        if edit.half_extends.x <= 0.0 || edit.half_extends.y <= 0.0 {
+            problem(Some(e), format!("zero-sized collider: {:?}", edit.half_extends));
+        }
+    }
+    for (e, circle) in world.query::<(Entity, &EditableCircle)>().iter(world) {
+        if circle.radius <= 0.0 {
+            problem(Some(e), format!("zero-sized circle collider: radius {}", circle.radius));
+        }
+    }
+    for (e, polygon) in world.query::<(Entity, &EditablePolygon)>().iter(world) {
+        if polygon.points.len() < 3 {
+            problem(Some(e), format!("polygon collider with only {} points", polygon.points.len()));
+        }
+    }
+
+    let walls: Vec<(Entity, ColliderGeometry)> = world
+        .query_filtered::<(
+            Entity,
+            Option<&EditableCollider>,
+            Option<&EditableCircle>,
+            Option<&EditablePolygon>,
+        ), With<Wall>>()
+        .iter(world)
+        .filter_map(|(e, cuboid, circle, polygon)| {
+            Some((e, ColliderGeometry::from_editable(cuboid, circle, polygon)?))
+        })
+        .collect();
+    // walls can be children; compare them all in world space
+    let walls: Vec<(Entity, ConvexShape)> = walls.into_iter()
+        .filter_map(|(e, geometry)| Some((e, ConvexShape::new(&world_transform(world, e)?, &geometry))))
+        .collect();
+    for (i, (e1, shape1)) in walls.iter().enumerate() {
+        for (e2, shape2) in walls[i + 1..].iter() {
+            if shape1.overlaps(shape2, WALL_OVERLAP_TOLERANCE) {
+                let other = scene_ids.get(e2).map(|id| id.to_string()).unwrap_or_default();
+                problem(Some(*e1), format!("wall overlaps wall {}", other));
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+/// The transform of an entity in world space, from its own and its ancestors'
+///
+/// The lint world is never run, so `GlobalTransform` is not up to date.
+fn world_transform(world: &World, e: Entity) -> Option<Transform> {
+    let mut xf = *world.get::<Transform>(e)?;
+    let mut current = e;
+    while let Some(parent) = world.get::<Parent>(current) {
+        if let Some(parent_xf) = world.get::<Transform>(parent.0) {
+            xf = parent_xf.mul_transform(xf);
+        }
+        current = parent.0;
+    }
+    Some(xf)
+}
+
+/// The outline of a wall in world space, for the overlap test
+///
+/// Circles are approximated with the same polygon the game uses for them.
+struct ConvexShape {
+    points: Vec<Vec2>,
+}
+
+impl ConvexShape {
+    fn new(xf: &Transform, geometry: &ColliderGeometry) -> Self {
+        let local = match geometry {
+            ColliderGeometry::Cuboid { half_extends } => vec![
+                Vec2::new(-half_extends.x, -half_extends.y),
+                Vec2::new(half_extends.x, -half_extends.y),
+                Vec2::new(half_extends.x, half_extends.y),
+                Vec2::new(-half_extends.x, half_extends.y),
+            ],
+            ColliderGeometry::Circle { radius } => ColliderGeometry::circle_points(*radius),
+            ColliderGeometry::Polygon { points } => points.clone(),
+        };
+        ConvexShape {
+            points: local.iter()
+                .map(|p| xf.mul_vec3(p.extend(0.0)).truncate())
+                .collect(),
+        }
+    }
+
+    /// Normals of the edges; the candidate separating axes
+    fn axes(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let n = self.points.len();
+        (0..n).filter_map(move |i| {
+            let edge = self.points[(i + 1) % n] - self.points[i];
+            edge.perp().try_normalize()
+        })
+    }
+
+    /// (min, max) of the projection of the shape onto an axis
+    fn project(&self, axis: Vec2) -> (f32, f32) {
+        self.points.iter()
+            .map(|p| p.dot(axis))
+            .fold((f32::MAX, f32::MIN), |(min, max), d| (min.min(d), max.max(d)))
+    }
+
+    /// separating axis test
+    fn overlaps(&self, other: &ConvexShape, tolerance: f32) -> bool {
+        if self.points.len() < 3 || other.points.len() < 3 {
+            return false;
+        }
+        self.axes().chain(other.axes()).all(|axis| {
+            let (min1, max1) = self.project(axis);
+            let (min2, max2) = other.project(axis);
+            max1.min(max2) - min1.max(min2) > tolerance
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::scene::DynamicEntity;
+
+    fn square(x: f32, y: f32, angle: f32) -> ConvexShape {
+        let xf = Transform::from_xyz(x, y, 0.0)
+            .with_rotation(Quat::from_rotation_z(angle));
+        ConvexShape::new(&xf, &ColliderGeometry::Cuboid { half_extends: Vec2::splat(10.0) })
+    }
+
+    fn circle(x: f32, y: f32) -> ConvexShape {
+        ConvexShape::new(&Transform::from_xyz(x, y, 0.0), &ColliderGeometry::Circle { radius: 10.0 })
+    }
+
+    fn entity(id: u32, x: f32, y: f32, components: Vec<Box<dyn Reflect>>) -> DynamicEntity {
+        let mut components = components;
+        components.push(Box::new(Transform::from_xyz(x, y, 0.0)));
+        DynamicEntity {
+            entity: id,
+            components,
+        }
+    }
+
+    fn lint(entities: Vec<DynamicEntity>) -> Vec<String> {
+        let mut app = lint_app();
+        let scene = DynamicScene { entities };
+        lint_scene(&mut app.world, &scene).unwrap()
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    /// Everything a level needs, away from the origin
+    fn level() -> Vec<DynamicEntity> {
+        vec![
+            entity(0, 100.0, 0.0, vec![Box::new(WinZone), Box::new(EditableCollider::default())]),
+            entity(1, 200.0, 0.0, vec![Box::new(SpawnZone), Box::new(EditableCollider::default())]),
+            entity(2, 300.0, 0.0, vec![Box::new(PlayerStart)]),
+            entity(3, 400.0, 0.0, vec![Box::new(Medkit::default())]),
+        ]
+    }
+
+    #[test]
+    fn touching_walls_do_not_overlap() {
+        assert!(!square(0.0, 0.0, 0.0).overlaps(&square(20.0, 0.0, 0.0), WALL_OVERLAP_TOLERANCE));
+        assert!(!square(0.0, 0.0, 0.0).overlaps(&square(20.0, 20.0, 0.0), WALL_OVERLAP_TOLERANCE));
+    }
+
+    #[test]
+    fn overlapping_walls() {
+        assert!(square(0.0, 0.0, 0.0).overlaps(&square(15.0, 0.0, 0.0), WALL_OVERLAP_TOLERANCE));
+        assert!(square(0.0, 0.0, 0.0).overlaps(&square(0.0, 0.0, 0.0), WALL_OVERLAP_TOLERANCE));
+    }
+
+    #[test]
+    fn rotated_wall_and_circle() {
+        assert!(!square(0.0, 0.0, 0.0).overlaps(&circle(22.0, 0.0), WALL_OVERLAP_TOLERANCE));
+        // the corners of the rotated box reach past 14 units
+        let rotated = square(0.0, 0.0, std::f32::consts::FRAC_PI_4);
+        assert!(rotated.overlaps(&circle(22.0, 0.0), WALL_OVERLAP_TOLERANCE));
+        assert!(circle(22.0, 0.0).overlaps(&rotated, WALL_OVERLAP_TOLERANCE));
+    }
+
+    #[test]
+    fn complete_level_has_no_problems() {
+        assert_eq!(lint(level()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn missing_level_entities() {
+        let problems = lint(vec![entity(0, 10.0, 0.0, vec![Box::new(Medkit::default())])]);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.contains("no WinZone")));
+        assert!(problems.iter().any(|p| p.contains("no SpawnZone")));
+        assert!(problems.iter().any(|p| p.contains("no PlayerStart")));
+    }
+
+    #[test]
+    fn pickup_at_origin() {
+        let mut entities = level();
+        entities.push(entity(7, 0.0, 0.0, vec![Box::new(AmmoBox::default())]));
+        assert_eq!(lint(entities), vec!["entity 7: pickup at the origin will be despawned".to_owned()]);
+    }
+
+    #[test]
+    fn overlapping_walls_in_scene() {
+        let wall = |id, x| {
+            entity(id, x, 0.0, vec![
+                Box::new(Wall),
+                Box::new(EditableCollider { half_extends: Vec2::splat(10.0) }),
+            ])
+        };
+        let mut entities = level();
+        entities.push(wall(10, 1000.0));
+        // touching the first one
+        entities.push(wall(11, 1020.0));
+        // across both
+        entities.push(wall(12, 1010.0));
+
+        let problems = lint(entities);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().all(|p| p.contains("wall overlaps wall")));
+        assert!(problems.iter().any(|p| p.contains("12")));
+    }
+}
@@ -2,47 +2,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use bevy::prelude::*;
-use bevy_asset_loader::{AssetLoader, AssetCollection};
-use game::shooting::*;
+use bevy_asset_loader::AssetLoader;
 
 use enum_iterator::IntoEnumIterator;
 use iyes_bevy_util::remove_resource;
 
-const GAMENAME: &str = "Absconder";
-
-mod editor;
-mod game;
-mod ui;
-mod util;
-mod scene_exporter;
-
-/// Each level/map in the game
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
-#[derive(IntoEnumIterator)]
-pub enum GameMode {
-    Scenario1,
-    DevPlayground,
-}
-
-/// Application states
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
-pub enum AppState {
-    MainAssetLoading,
-    MainMenu,
-    GameAssetLoading(GameMode),
-    InGame(GameMode),
-    GameOver,
-    Credits,
-    DevEditor,
-}
-
-/// Cant we have stageless already! :yeet:
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[derive(StageLabel)]
-enum FuckStages {
-    Pre,
-    Post,
-}
+use bevyjam::*;
 
 fn main() {
     let mut app = App::new();
@@ -115,6 +80,8 @@ fn main() {
     app.add_plugin(util::UtilPlugin);
     app.add_plugin(editor::DevEditorPlugin);
     app.add_plugin(game::blueprints::BlueprintsPlugin);
+    app.add_plugin(game::savegame::SaveGamePlugin);
+    app.add_plugin(game::tiled::TiledPlugin);
     app.add_plugin(scene_exporter::SerializePlugin);
 
     // debug systems; uncomment if needed
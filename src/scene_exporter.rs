@@ -1,23 +1,79 @@
-use std::path::PathBuf;
+use std::any::TypeId;
+use std::path::{Path, PathBuf};
 
+use bevy::ecs::entity::EntityMap;
 use bevy::ecs::schedule::ShouldRun;
 use bevy::ecs::system::{CommandQueue, SystemParam, SystemState};
+use bevy::asset::{AssetLoader, FileAssetIo, LoadContext, LoadedAsset};
 use bevy::prelude::*;
-use bevy::reflect::TypeRegistry;
+use bevy::reflect::{ReflectMut, TypeRegistry};
 use bevy::reflect::TypeRegistryArc;
 use bevy::scene::DynamicEntity;
+use bevy::scene::serde::SceneDeserializer;
 use bevy::ecs::event::Events;
+use bevy::utils::BoxedFuture;
+use serde::de::DeserializeSeed;
 
 use crate::game::blueprints::ExportableTypes;
 use crate::{AppState, FuckStages};
 
+pub mod migrate;
+pub mod prefab;
+pub mod stable_id;
+
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct SaveSceneMarker;
+/// Entities that come from somewhere other than the scene (like a Tiled map)
+///
+/// They are never saved with the scene, or despawned when another scene is
+/// loaded, even if they have a `SaveSceneMarker`.
+#[derive(Component)]
+pub struct ExcludeFromScene;
+/// Where to save the scene, relative to the assets directory
 pub struct SaveScenePath(pub PathBuf);
 pub struct SaveSceneEvent;
+/// Replace all scene entities with the contents of the given `.scn.ron` file
+/// (path relative to the assets directory)
+pub struct LoadSceneEvent(pub PathBuf);
+/// Despawn all scene entities, to start a new scene from scratch
+pub struct ClearSceneEvent;
+
+const ASSETS_DIR: &str = "assets";
+
+/// Location on disk of a file in the assets directory
+pub fn asset_fs_path(path: &Path) -> PathBuf {
+    FileAssetIo::get_root_path().join(ASSETS_DIR).join(path)
+}
 
-fn has_save_event(e: Res<Events<SaveSceneEvent>>) -> ShouldRun {
+/// Find all `.scn.ron` files in the assets directory
+///
+/// Returned paths are relative to the assets directory, sorted.
+pub fn find_scene_files() -> Vec<PathBuf> {
+    fn walk(dir: &Path, base: &Path, out: &mut Vec<PathBuf>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(&path, base, out);
+            } else if path.to_string_lossy().ends_with(".scn.ron") {
+                if let Ok(rel) = path.strip_prefix(base) {
+                    out.push(rel.to_owned());
+                }
+            }
+        }
+    }
+    let base = asset_fs_path(Path::new(""));
+    let mut files = Vec::new();
+    walk(&base, &base, &mut files);
+    files.sort();
+    files
+}
+
+pub(crate) fn has_event<T: Send + Sync + 'static>(e: Res<Events<T>>) -> ShouldRun {
     let mut result = ShouldRun::No;
     if !e.is_empty() {
         result = ShouldRun::Yes;
@@ -29,23 +85,49 @@ fn save_scene(world: &mut World) {
     let path = world.get_resource::<SaveScenePath>().unwrap().0.clone();
     world.get_resource_mut::<Events<SaveSceneEvent>>().unwrap().clear();
 
+    let scene = snapshot_scene(world);
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap();
+    let scene = serialize_scene(&scene, type_registry).unwrap();
+
+    let fs_path = asset_fs_path(&path);
+    if let Some(dir) = fs_path.parent() {
+        std::fs::create_dir_all(dir).ok();
+    }
+    match std::fs::write(&fs_path, scene) {
+        Ok(()) => info!("Scene Saved to {:?}", path),
+        Err(e) => error!("Could not save scene to {:?}: {}", path, e),
+    }
+}
+
+/// Build a scene out of all the scene entities currently in the world
+pub fn snapshot_scene(world: &mut World) -> DynamicScene {
     let mut ss = SystemState::<(
         Res<ExportableTypes>,
-        Query<Entity, With<SaveSceneMarker>>
+        Query<Entity, (With<SaveSceneMarker>, Without<ExcludeFromScene>)>
     )>::new(world);
     let (et, q) = ss.get(world);
     let entities = q.iter().collect();
 
     let type_registry = world.get_resource::<TypeRegistry>().unwrap();
-    let scene = scene_from_entities(world, type_registry, entities, &*et);
-    let scene = scene.serialize_ron(type_registry).unwrap();
+    scene_from_entities(world, type_registry, entities, &*et)
+}
 
-    match std::fs::write(&path, scene) {
-        Ok(()) => info!("Scene Saved to {:?}", path),
-        Err(e) => error!("Could not save scene to {:?}: {}", path, e),
+/// Replace all scene entities with the contents of a scene taken with `snapshot_scene`
+///
+/// Unlike loading a scene file, this happens immediately.
+pub fn restore_scene(world: &mut World, scene: &DynamicScene) {
+    despawn_scene_entities(world);
+    if let Err(e) = scene.write_to_world(world, &mut EntityMap::default()) {
+        error!("Could not restore scene: {:?}", e);
     }
 }
 
+/// Build a scene out of the given entities
+///
+/// Parent/child relationships between the entities are preserved, along with
+/// their local transforms. `Children` is never exported; bevy rebuilds it from
+/// `Parent` after loading. An entity whose parent is not part of the scene is
+/// exported at the top level, with its global transform.
 pub fn scene_from_entities(
     world: &World,
     type_registry: &TypeRegistryArc,
@@ -55,6 +137,13 @@ pub fn scene_from_entities(
     let mut scene = DynamicScene::default();
     let type_registry = type_registry.read();
 
+    // has a parent that will not be in the scene
+    let detached = |e: Entity| {
+        world.get::<Parent>(e)
+            .map(|parent| !entities.contains(&parent.0))
+            .unwrap_or(false)
+    };
+
     for archetype in world.archetypes().iter() {
         let entities_offset = scene.entities.len();
 
@@ -86,6 +175,21 @@ pub fn scene_from_entities(
                     .filter(|e| entities.contains(e))
                     .enumerate()
                 {
+                    if detached(*entity) {
+                        let type_id = world.components().get_info(component_id)
+                            .and_then(|info| info.type_id());
+                        if type_id == Some(TypeId::of::<Parent>()) {
+                            continue;
+                        }
+                        if type_id == Some(TypeId::of::<Transform>()) {
+                            if let Some(gxf) = world.get::<GlobalTransform>(*entity) {
+                                scene.entities[entities_offset + i]
+                                    .components
+                                    .push(Box::new(Transform::from(*gxf)));
+                                continue;
+                            }
+                        }
+                    }
                     if let Some(component) = reflect_component.reflect_component(world, *entity) {
                         scene.entities[entities_offset + i]
                             .components
@@ -108,17 +212,258 @@ fn save_scene_on_key(
     }
 }
 
-/*
-fn load_scene(
-    asset_server: Res<AssetServer>,
-    mut scene_spawner: ResMut<SceneSpawner>,
-    mut state: ResMut<State<GameState>>,
+fn load_scene_on_key(
+    input: Res<Input<KeyCode>>,
+    path: Res<SaveScenePath>,
+    mut evw: EventWriter<LoadSceneEvent>,
 ) {
-    let scene_handle = asset_server.load("levels/level1.scn.ron");
-    scene_spawner.spawn_dynamic(scene_handle);
-    state.replace(GameState::PostLoadLevel).unwrap();
+    if input.just_pressed(KeyCode::F9) {
+        evw.send(LoadSceneEvent(path.0.clone()));
+    }
+}
+
+/// Serialize a scene, stamped with the current format version
+pub fn serialize_scene(
+    scene: &DynamicScene,
+    type_registry: &TypeRegistryArc,
+) -> Result<String, ron::Error> {
+    let text = scene.serialize_ron(type_registry)?;
+    Ok(migrate::version_header() + &text)
+}
+
+/// Deserialize a scene, upgrading it from older format versions if needed
+pub fn deserialize_scene(
+    type_registry: &TypeRegistryArc,
+    bytes: &[u8],
+) -> Result<DynamicScene, String> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|e| e.to_string())?;
+    let version = migrate::parse_version(text)?;
+    if version > migrate::SCENE_FORMAT_VERSION {
+        return Err(format!(
+            "Scene format version {} is newer than supported ({})",
+            version, migrate::SCENE_FORMAT_VERSION
+        ));
+    }
+    let text = migrate::migrate_types(text, version);
+
+    let mut deserializer = ron::de::Deserializer::from_str(&text)
+        .map_err(|e| e.to_string())?;
+    let type_registry = type_registry.read();
+    let scene_deserializer = SceneDeserializer {
+        type_registry: &*type_registry,
+    };
+    let mut scene = scene_deserializer.deserialize(&mut deserializer)
+        .map_err(|e| e.to_string())?;
+    migrate::migrate_scene(&mut scene, version);
+    Ok(scene)
+}
+
+/// Read and deserialize a scene file from disk
+///
+/// Bypasses the `AssetServer`, so that we always get the latest version
+/// of the file, even if it was loaded before.
+pub fn load_scene_file(
+    type_registry: &TypeRegistryArc,
+    path: &Path,
+) -> Result<DynamicScene, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("Could not read scene {:?}: {}", path, e))?;
+    deserialize_scene(type_registry, &bytes)
+        .map_err(|e| format!("Could not parse scene {:?}: {}", path, e))
+}
+
+/// Replaces bevy's own loader for `.scn.ron`, so that scenes loaded
+/// as assets get migrated too
+pub struct MigratingSceneLoader {
+    type_registry: TypeRegistryArc,
+}
+
+impl FromWorld for MigratingSceneLoader {
+    fn from_world(world: &mut World) -> Self {
+        MigratingSceneLoader {
+            type_registry: world.get_resource::<TypeRegistry>().unwrap().clone(),
+        }
+    }
+}
+
+impl AssetLoader for MigratingSceneLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let scene = deserialize_scene(&self.type_registry, bytes)
+                .map_err(anyhow::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(scene));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scn.ron"]
+    }
+}
+
+/// The given entities and all their descendants that would be saved with the scene
+pub fn scene_subtree(world: &World, roots: &[Entity]) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    let mut stack = roots.to_vec();
+    while let Some(e) = stack.pop() {
+        if entities.contains(&e) {
+            continue;
+        }
+        if world.get::<SaveSceneMarker>(e).is_none() || world.get::<ExcludeFromScene>(e).is_some() {
+            continue;
+        }
+        entities.push(e);
+        if let Some(children) = world.get::<Children>(e) {
+            stack.extend(children.iter().copied());
+        }
+    }
+    entities
+}
+
+/// Export the given entities (and their descendants), centered on their average position
+///
+/// The top-level transforms in the scene are relative to the center, which is also returned.
+/// `None` if none of the entities would be saved with the scene.
+pub fn centered_scene(world: &World, roots: &[Entity]) -> Option<(DynamicScene, Vec2)> {
+    let entities = scene_subtree(world, roots);
+    let top_level: Vec<Entity> = entities.iter()
+        .copied()
+        .filter(|e| world.get::<Parent>(*e).map(|p| !entities.contains(&p.0)).unwrap_or(true))
+        .collect();
+    if top_level.is_empty() {
+        return None;
+    }
+
+    let origin = top_level.iter()
+        .filter_map(|e| world.get::<GlobalTransform>(*e))
+        .map(|gxf| gxf.translation.truncate())
+        .fold(Vec2::ZERO, |acc, pos| acc + pos) / top_level.len() as f32;
+
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap();
+    let et = world.get_resource::<ExportableTypes>().unwrap();
+    let mut scene = scene_from_entities(world, type_registry, entities, et);
+
+    // make the top level transforms relative to the origin
+    let parent_type = std::any::type_name::<Parent>();
+    let transform_type = std::any::type_name::<Transform>();
+    for entity in scene.entities.iter_mut() {
+        if entity.components.iter().any(|c| c.type_name() == parent_type) {
+            continue;
+        }
+        for component in entity.components.iter_mut() {
+            if component.type_name() != transform_type {
+                continue;
+            }
+            if let ReflectMut::Struct(s) = component.reflect_mut() {
+                if let Some(translation) = s.field_mut("translation")
+                    .and_then(|t| t.downcast_mut::<Vec3>())
+                {
+                    translation.x -= origin.x;
+                    translation.y -= origin.y;
+                }
+            }
+        }
+    }
+    Some((scene, origin))
+}
+
+/// A copy of a scene entity (and its descendants), that can be spawned again later
+pub struct EntitySnapshot {
+    scene: DynamicScene,
+    /// The entities the snapshot was taken from; the root is first
+    entities: Vec<Entity>,
+    parent: Option<Entity>,
+    transform: Transform,
+}
+
+impl EntitySnapshot {
+    pub fn take(world: &World, root: Entity) -> Option<EntitySnapshot> {
+        let transform = *world.get::<Transform>(root)?;
+        let mut entities = scene_subtree(world, &[root]);
+        if entities.first() != Some(&root) {
+            return None;
+        }
+        let type_registry = world.get_resource::<TypeRegistry>().unwrap();
+        let et = world.get_resource::<ExportableTypes>().unwrap();
+        let scene = scene_from_entities(world, type_registry, entities.clone(), et);
+        // entities that did not make it into the scene
+        entities.retain(|e| scene.entities.iter().any(|de| de.entity == e.id()));
+        Some(EntitySnapshot {
+            scene,
+            entities,
+            parent: world.get::<Parent>(root).map(|p| p.0),
+            transform,
+        })
+    }
+
+    /// Spawn the snapshot, back under its old parent if it still exists
+    ///
+    /// Returns the pairs of (old, new) entities.
+    pub fn spawn(&self, world: &mut World) -> Vec<(Entity, Entity)> {
+        let mut entity_map = EntityMap::default();
+        if let Err(e) = self.scene.write_to_world(world, &mut entity_map) {
+            error!("Could not respawn entity: {:?}", e);
+            return Vec::new();
+        }
+        let mapping: Vec<(Entity, Entity)> = self.entities.iter()
+            .filter_map(|old| entity_map.get(Entity::from_raw(old.id())).ok().map(|new| (*old, new)))
+            .collect();
+        if let (Some(parent), Some((_, root))) = (self.parent, mapping.first()) {
+            if world.get_entity(parent).is_some() {
+                world.entity_mut(*root).insert(self.transform);
+                world.entity_mut(parent).push_children(&[*root]);
+            }
+        }
+        mapping
+    }
+}
+
+/// Despawn everything that would be exported with the scene
+pub fn despawn_scene_entities(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, (With<SaveSceneMarker>, Without<ExcludeFromScene>)>()
+        .iter(world)
+        .collect();
+    for e in entities {
+        // might already be gone, if it was a child of another scene entity
+        if let Some(emut) = world.get_entity_mut(e) {
+            emut.despawn_recursive();
+        }
+    }
+}
+
+fn load_scene(world: &mut World) {
+    let path = world.get_resource_mut::<Events<LoadSceneEvent>>().unwrap()
+        .drain()
+        .last()
+        .unwrap()
+        .0;
+
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap().clone();
+    let scene = match load_scene_file(&type_registry, &asset_fs_path(&path)) {
+        Ok(scene) => scene,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    despawn_scene_entities(world);
+
+    let handle = world.get_resource_mut::<Assets<DynamicScene>>().unwrap().add(scene);
+    world.get_resource_mut::<SceneSpawner>().unwrap().spawn_dynamic(handle);
+    info!("Scene Loaded from {:?}", path);
+}
+
+fn clear_scene(world: &mut World) {
+    world.get_resource_mut::<Events<ClearSceneEvent>>().unwrap().clear();
+    despawn_scene_entities(world);
 }
-*/
 
 pub struct SerializePlugin;
 impl Plugin for SerializePlugin {
@@ -126,10 +471,44 @@ impl Plugin for SerializePlugin {
         app.add_system_to_stage(FuckStages::Post,
             save_scene
                 .exclusive_system()
-                .with_run_criteria(has_save_event),
+                .with_run_criteria(has_event::<SaveSceneEvent>),
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            load_scene
+                .exclusive_system()
+                .with_run_criteria(has_event::<LoadSceneEvent>),
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            clear_scene
+                .exclusive_system()
+                .with_run_criteria(has_event::<ClearSceneEvent>),
+        );
+        app.add_system_to_stage(FuckStages::Post,
+            prefab::save_prefab
+                .exclusive_system()
+                .with_run_criteria(has_event::<prefab::SavePrefabEvent>),
+        );
+        app.add_system_to_stage(FuckStages::Post, prefab::init_prefab_instances);
+        app.add_system_to_stage(FuckStages::Post,
+            prefab::instantiate_prefabs
+                .exclusive_system()
+                .at_end(),
         );
+        app.add_system(prefab::refresh_linked_instances);
+        // after the scene spawner, so loaded entities keep their IDs
+        app.add_system_to_stage(FuckStages::Pre, stable_id::assign_stable_ids);
+        app.add_system(stable_id::index_stable_ids.label("index_stable_ids"));
+        app.init_resource::<stable_id::StableIds>();
+        app.add_event::<prefab::SavePrefabEvent>();
+        app.add_event::<prefab::PrefabSavedEvent>();
+        // must come after bevy's `ScenePlugin`, to take over `.scn.ron` files
+        app.init_asset_loader::<MigratingSceneLoader>();
         app.add_event::<SaveSceneEvent>();
-        app.insert_resource(SaveScenePath("test.ron".into()));
+        app.add_event::<LoadSceneEvent>();
+        app.add_event::<ClearSceneEvent>();
+        // scenarios replace this with the path of their own scene
+        app.insert_resource(SaveScenePath("test.scn.ron".into()));
         app.add_system(save_scene_on_key);
+        app.add_system(load_scene_on_key);
     }
 }
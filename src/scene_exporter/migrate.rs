@@ -0,0 +1,285 @@
+//! Keeping old scene files loadable after refactors
+//!
+//! Every exported scene starts with a header comment recording the format
+//! version it was written with. When loading an older scene, all migrations
+//! newer than its version are applied, in order. Files without a header
+//! are version 0.
+//!
+//! # HOW TO CHANGE A COMPONENT THAT IS SAVED IN SCENES
+//!
+//! - bump `SCENE_FORMAT_VERSION`
+//! - add a `Migration` to `MIGRATIONS`, with `version` set to the new version
+//!   - renamed or moved types go in `renamed_types` (full type names, as they
+//!     appear in the scene files)
+//!   - anything else (renamed fields, changed units, ...) goes in `fixup`;
+//!     see `rename_field` for an example
+//!
+
+use std::borrow::Cow;
+
+use bevy::prelude::*;
+use bevy::reflect::DynamicStruct;
+use bevy::reflect::Struct;
+use bevy::scene::DynamicEntity;
+
+use crate::game::blueprints::PlayerStart;
+use crate::game::collider::{SpawnZone, WinZone};
+
+/// Version written into newly exported scenes
+pub const SCENE_FORMAT_VERSION: u32 = 2;
+
+/// Prefix of the first line of a scene file (it's a RON comment)
+const VERSION_HEADER: &str = "// scene format version: ";
+
+pub struct Migration {
+    /// The format version this migration upgrades to
+    pub version: u32,
+    /// (old, new) full type names
+    pub renamed_types: &'static [(&'static str, &'static str)],
+    /// Applied to the deserialized scene, after types were renamed
+    pub fixup: Option<fn(&mut DynamicScene)>,
+}
+
+/// All migrations, in order of version
+pub static MIGRATIONS: &[Migration] = &[
+    // version 1 only added the version header, nothing to do
+    Migration {
+        version: 1,
+        renamed_types: &[],
+        fixup: None,
+    },
+    Migration {
+        version: 2,
+        renamed_types: &[],
+        fixup: Some(add_player_start),
+    },
+];
+
+pub fn version_header() -> String {
+    format!("{}{}\n", VERSION_HEADER, SCENE_FORMAT_VERSION)
+}
+
+/// Read the format version from the header of a scene file
+pub fn parse_version(text: &str) -> Result<u32, String> {
+    match text.lines().next().and_then(|line| line.strip_prefix(VERSION_HEADER)) {
+        Some(v) => v.trim().parse()
+            .map_err(|_| format!("Invalid scene format version: {:?}", v)),
+        None => Ok(0),
+    }
+}
+
+/// Apply type renames; must happen before deserializing,
+/// because unknown type names are an error
+pub fn migrate_types(text: &str, from: u32) -> Cow<str> {
+    migrate_types_with(MIGRATIONS, text, from)
+}
+
+fn migrate_types_with<'a>(migrations: &[Migration], text: &'a str, from: u32) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for migration in migrations.iter().filter(|m| m.version > from) {
+        for (old, new) in migration.renamed_types {
+            let old = format!("\"{}\"", old);
+            if text.contains(&old) {
+                text = Cow::Owned(text.replace(&old, &format!("\"{}\"", new)));
+            }
+        }
+    }
+    text
+}
+
+/// Apply fixups to a freshly deserialized scene
+pub fn migrate_scene(scene: &mut DynamicScene, from: u32) {
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        if let Some(fixup) = migration.fixup {
+            fixup(scene);
+        }
+    }
+}
+
+/// Call `f` for every (deserialized) instance of the given component type
+pub fn for_each_component(
+    scene: &mut DynamicScene,
+    type_name: &str,
+    mut f: impl FnMut(&mut DynamicStruct),
+) {
+    for entity in scene.entities.iter_mut() {
+        for component in entity.components.iter_mut() {
+            if component.type_name() != type_name {
+                continue;
+            }
+            if let Some(s) = component.downcast_mut::<DynamicStruct>() {
+                f(s);
+            }
+        }
+    }
+}
+
+/// Fixup helper: rename a field of a struct component
+#[allow(dead_code)]
+pub fn rename_field(scene: &mut DynamicScene, type_name: &str, old: &str, new: &str) {
+    for_each_component(scene, type_name, |s| {
+        let mut renamed = DynamicStruct::default();
+        renamed.set_name(s.name().to_owned());
+        for i in 0..s.field_len() {
+            let name = s.name_at(i).unwrap();
+            let name = if name == old { new } else { name };
+            renamed.insert_boxed(name, s.field_at(i).unwrap().clone_value());
+        }
+        *s = renamed;
+    });
+}
+
+/// Version 2: the player used to always start at this hardcoded position;
+/// levels (scenes with win or spawn zones, not prefabs) get a `PlayerStart` there
+fn add_player_start(scene: &mut DynamicScene) {
+    let level_types = [std::any::type_name::<WinZone>(), std::any::type_name::<SpawnZone>()];
+    let is_level = scene.entities.iter()
+        .flat_map(|entity| entity.components.iter())
+        .any(|component| level_types.contains(&component.type_name()));
+    if !is_level {
+        return;
+    }
+    let id = scene.entities.iter().map(|entity| entity.entity + 1).max().unwrap_or(0);
+    scene.entities.push(DynamicEntity {
+        entity: id,
+        components: vec![
+            Box::new(Transform::from_xyz(-2982.9265, 1052.7454, 2.0)),
+            Box::new(PlayerStart),
+        ],
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::game::blueprints::Medkit;
+    use crate::scene_exporter::prefab::PrefabInstance;
+
+    static RENAMES: &[Migration] = &[
+        Migration {
+            version: 3,
+            renamed_types: &[("game::OldThing", "game::NewThing")],
+            fixup: None,
+        },
+    ];
+
+    const RENAME_SCENE: &str = r#"(
+  entities: [
+    (
+      entity: 0,
+      components: [
+        {
+          "type": "game::OldThing",
+          "struct": {},
+        },
+      ],
+    ),
+  ],
+)"#;
+
+    fn entity(id: u32, components: Vec<Box<dyn Reflect>>) -> DynamicEntity {
+        DynamicEntity {
+            entity: id,
+            components,
+        }
+    }
+
+    fn count_player_starts(scene: &DynamicScene) -> usize {
+        scene.entities.iter()
+            .flat_map(|entity| entity.components.iter())
+            .filter(|component| component.type_name() == std::any::type_name::<PlayerStart>())
+            .count()
+    }
+
+    #[test]
+    fn no_header_is_version_0() {
+        assert_eq!(parse_version(RENAME_SCENE), Ok(0));
+        assert_eq!(parse_version(""), Ok(0));
+    }
+
+    #[test]
+    fn header_is_parsed() {
+        let text = format!("{}{}", version_header(), RENAME_SCENE);
+        assert_eq!(parse_version(&text), Ok(SCENE_FORMAT_VERSION));
+    }
+
+    #[test]
+    fn bad_header_is_an_error() {
+        let text = format!("{}two\n{}", VERSION_HEADER, RENAME_SCENE);
+        assert!(parse_version(&text).is_err());
+    }
+
+    #[test]
+    fn types_are_renamed_for_older_versions_only() {
+        let migrated = migrate_types_with(RENAMES, RENAME_SCENE, 2);
+        assert!(migrated.contains("\"game::NewThing\""));
+        assert!(!migrated.contains("\"game::OldThing\""));
+
+        let migrated = migrate_types_with(RENAMES, RENAME_SCENE, 3);
+        assert_eq!(migrated, RENAME_SCENE);
+    }
+
+    #[test]
+    fn fields_are_renamed() {
+        let mut component = DynamicStruct::default();
+        component.set_name("game::Thing".to_owned());
+        component.insert("old", 1.5f32);
+        component.insert("other", 2u32);
+        let mut scene = DynamicScene {
+            entities: vec![entity(0, vec![Box::new(component)])],
+        };
+
+        rename_field(&mut scene, "game::Thing", "old", "new");
+
+        let renamed = scene.entities[0].components[0].downcast_ref::<DynamicStruct>().unwrap();
+        assert_eq!(renamed.field("new").and_then(|f| f.downcast_ref::<f32>()), Some(&1.5));
+        assert_eq!(renamed.field("other").and_then(|f| f.downcast_ref::<u32>()), Some(&2));
+        assert!(renamed.field("old").is_none());
+    }
+
+    #[test]
+    fn player_start_is_added_to_levels() {
+        for zone in [Box::new(WinZone) as Box<dyn Reflect>, Box::new(SpawnZone)] {
+            let mut scene = DynamicScene {
+                entities: vec![
+                    entity(0, vec![Box::new(Transform::identity()), zone]),
+                    entity(4, vec![Box::new(Transform::identity()), Box::new(Medkit::default())]),
+                ],
+            };
+
+            migrate_scene(&mut scene, 1);
+
+            assert_eq!(count_player_starts(&scene), 1);
+            assert_eq!(scene.entities.len(), 3);
+            // a new entity, not reusing an id
+            assert_eq!(scene.entities[2].entity, 5);
+        }
+    }
+
+    #[test]
+    fn player_start_is_not_added_to_prefabs() {
+        let mut scene = DynamicScene {
+            entities: vec![
+                entity(0, vec![Box::new(Transform::identity()), Box::new(Medkit::default())]),
+                entity(1, vec![Box::new(Transform::identity()), Box::new(PrefabInstance::default())]),
+            ],
+        };
+
+        migrate_scene(&mut scene, 1);
+
+        assert_eq!(count_player_starts(&scene), 0);
+        assert_eq!(scene.entities.len(), 2);
+    }
+
+    #[test]
+    fn current_scenes_are_not_migrated() {
+        let mut scene = DynamicScene {
+            entities: vec![entity(0, vec![Box::new(Transform::identity()), Box::new(WinZone)])],
+        };
+
+        migrate_scene(&mut scene, SCENE_FORMAT_VERSION);
+
+        assert_eq!(count_player_starts(&scene), 0);
+    }
+}
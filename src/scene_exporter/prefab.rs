@@ -0,0 +1,240 @@
+//! Prefabs: groups of scene entities saved to their own file, that can be
+//! placed into levels as instances
+//!
+//! Prefab files are ordinary scene files in `assets/prefabs`. An instance is an
+//! entity with a `PrefabInstance` component; the contents of the prefab are
+//! spawned as its children.
+//!
+//! Linked instances only save the `PrefabInstance` itself with the scene; their
+//! contents are spawned from the prefab file every time the scene is loaded,
+//! so changes to the prefab show up everywhere it is used.
+//! Unlinked instances save their contents with the scene, like any other
+//! entities, and are independent of the prefab after being placed.
+
+use std::path::{Path, PathBuf};
+
+use bevy::ecs::entity::EntityMap;
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
+
+use crate::game::blueprints::BlueprintKind;
+use crate::game::GameCleanup;
+
+use super::{asset_fs_path, centered_scene, load_scene_file, serialize_scene};
+use super::stable_id::assign_copied_ids;
+use super::{ExcludeFromScene, SaveSceneMarker};
+
+/// Where prefab files live, relative to the assets directory
+pub const PREFAB_DIR: &str = "prefabs";
+/// The `BlueprintKind` of prefab instances
+pub const PREFAB_KIND: &str = "Prefab";
+
+#[derive(Component, Reflect, Default, Clone)]
+#[reflect(Component)]
+pub struct PrefabInstance {
+    /// Path of the prefab file, relative to the assets directory
+    pub path: String,
+    pub linked: bool,
+}
+
+/// Spawned from the prefab of the given instance (root) entity
+#[derive(Component)]
+pub struct PrefabChild(pub Entity);
+
+/// The contents of this instance need to be (re)spawned
+#[derive(Component)]
+pub struct InstantiatePrefab;
+
+/// Save the given entities (and their descendants) as a prefab
+///
+/// The prefab is centered on the average position of the entities.
+pub struct SavePrefabEvent {
+    /// Relative to the assets directory
+    pub path: PathBuf,
+    pub entities: Vec<Entity>,
+}
+
+/// Sent after a prefab file has been written
+pub struct PrefabSavedEvent(pub PathBuf);
+
+/// Find all prefab files
+///
+/// Returned paths are relative to the assets directory, sorted.
+pub fn find_prefab_files() -> Vec<PathBuf> {
+    super::find_scene_files()
+        .into_iter()
+        .filter(|p| p.starts_with(PREFAB_DIR))
+        .collect()
+}
+
+/// Display name of a prefab file
+pub fn prefab_name(path: &Path) -> String {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    name.trim_end_matches(".scn.ron").to_owned()
+}
+
+pub(super) fn init_prefab_instances(
+    mut commands: Commands,
+    q: Query<(Entity, &PrefabInstance), Added<PrefabInstance>>,
+) {
+    for (e, instance) in q.iter() {
+        let mut cmd = commands.entity(e);
+        cmd.insert(SaveSceneMarker)
+            .insert(GameCleanup)
+            .insert(BlueprintKind(PREFAB_KIND))
+            .insert(GlobalTransform::default());
+        // unlinked instances had their contents saved with the scene
+        if instance.linked {
+            cmd.insert(InstantiatePrefab);
+        }
+    }
+}
+
+/// Spawn the contents of prefab instances that need it
+pub(super) fn instantiate_prefabs(world: &mut World) {
+    let todo: Vec<(Entity, PrefabInstance)> = world
+        .query_filtered::<(Entity, &PrefabInstance), With<InstantiatePrefab>>()
+        .iter(world)
+        .map(|(e, instance)| (e, instance.clone()))
+        .collect();
+    if todo.is_empty() {
+        return;
+    }
+
+    let type_registry = world.get_resource::<TypeRegistry>().unwrap().clone();
+    for (root, instance) in todo {
+        world.entity_mut(root).remove::<InstantiatePrefab>();
+
+        if is_recursive(world, root, &instance.path) {
+            error!("Prefab {:?} contains itself!", instance.path);
+            continue;
+        }
+
+        let scene = match load_scene_file(&type_registry, &asset_fs_path(Path::new(&instance.path))) {
+            Ok(scene) => scene,
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        let mut entity_map = EntityMap::default();
+        if let Err(e) = scene.write_to_world(world, &mut entity_map) {
+            error!("Could not spawn prefab {:?}: {:?}", instance.path, e);
+            continue;
+        }
+
+        let spawned: Vec<Entity> = entity_map.values().collect();
+        assign_copied_ids(world, &spawned);
+        let mut top_level = Vec::new();
+        for e in spawned {
+            let mut emut = world.entity_mut(e);
+            if !emut.contains::<Parent>() {
+                top_level.push(e);
+            }
+            emut.insert(PrefabChild(root));
+            if instance.linked {
+                emut.insert(ExcludeFromScene);
+            }
+        }
+        world.entity_mut(root).push_children(&top_level);
+    }
+}
+
+/// Is there an instance of the same prefab above this one in the hierarchy?
+fn is_recursive(world: &World, root: Entity, path: &str) -> bool {
+    let mut cur = root;
+    while let Some(parent) = world.get::<Parent>(cur) {
+        cur = parent.0;
+        if let Some(instance) = world.get::<PrefabInstance>(cur) {
+            if instance.path == path {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Respawn the contents of linked instances when their prefab is saved
+pub(super) fn refresh_linked_instances(
+    mut commands: Commands,
+    mut evr: EventReader<PrefabSavedEvent>,
+    q: Query<(Entity, &PrefabInstance)>,
+) {
+    for ev in evr.iter() {
+        for (e, instance) in q.iter() {
+            if instance.linked && Path::new(&instance.path) == ev.0 {
+                commands.entity(e).despawn_descendants();
+                commands.entity(e).insert(InstantiatePrefab);
+            }
+        }
+    }
+}
+
+/// Link or unlink a prefab instance
+///
+/// Unlinking keeps the contents, which become part of the scene.
+/// Linking throws away the contents and respawns them from the prefab.
+pub fn set_prefab_linked(
+    commands: &mut Commands,
+    root: Entity,
+    instance: &mut PrefabInstance,
+    linked: bool,
+    q_children: &Query<(Entity, &PrefabChild)>,
+) {
+    if instance.linked == linked {
+        return;
+    }
+    instance.linked = linked;
+    if linked {
+        commands.entity(root).despawn_descendants();
+        commands.entity(root).insert(InstantiatePrefab);
+    } else {
+        for (e, child) in q_children.iter() {
+            if child.0 == root {
+                commands.entity(e)
+                    .remove::<ExcludeFromScene>()
+                    .remove::<PrefabChild>();
+            }
+        }
+    }
+}
+
+pub(super) fn save_prefab(world: &mut World) {
+    let events: Vec<SavePrefabEvent> = world
+        .get_resource_mut::<Events<SavePrefabEvent>>().unwrap()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let scene = if let Some((scene, _)) = centered_scene(world, &ev.entities) {
+            scene
+        } else {
+            warn!("Nothing to save as prefab");
+            continue;
+        };
+
+        let type_registry = world.get_resource::<TypeRegistry>().unwrap();
+        let text = match serialize_scene(&scene, type_registry) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Could not serialize prefab {:?}: {}", ev.path, e);
+                continue;
+            }
+        };
+        let fs_path = asset_fs_path(&ev.path);
+        if let Some(dir) = fs_path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        match std::fs::write(&fs_path, text) {
+            Ok(()) => {
+                info!("Prefab saved to {:?}", ev.path);
+                world.get_resource_mut::<Events<PrefabSavedEvent>>().unwrap()
+                    .send(PrefabSavedEvent(ev.path));
+            }
+            Err(e) => error!("Could not save prefab to {:?}: {}", ev.path, e),
+        }
+    }
+}
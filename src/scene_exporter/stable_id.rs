@@ -0,0 +1,211 @@
+//! Stable IDs, so that scene entities can refer to each other
+//!
+//! `Entity` values are different every time a scene is spawned, so they
+//! can't be saved. Instead, every scene entity gets a random `StableId`,
+//! which is saved with it, and references are stored as `EntityRef`,
+//! which is saved as just the ID. After loading, the references are
+//! resolved back to the live entities.
+//!
+//! Copied groups of entities (pasted ones, or the contents of prefab
+//! instances) get new IDs with `assign_copied_ids`: references within the
+//! group point at the copies, references to anything else keep pointing at
+//! the original.
+
+use bevy::prelude::*;
+use bevy::reflect::FromReflect;
+use bevy::utils::{HashMap, HashSet};
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::SaveSceneMarker;
+
+/// Identifies a scene entity, across saving and loading
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct StableId(pub u64);
+
+impl StableId {
+    fn random(rng: &mut impl Rng) -> Self {
+        // zero means "no entity" in an `EntityRef`
+        StableId(rng.gen_range(1..=u64::MAX))
+    }
+}
+
+/// A reference to a scene entity, saved as its `StableId`
+///
+/// The entity is filled in when the references of the component holding it
+/// are resolved (see `EntityRefs`); it is `None` until then, or if the
+/// entity is gone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Reflect, FromReflect)]
+#[reflect_value(PartialEq, Serialize, Deserialize)]
+pub struct EntityRef {
+    id: u64,
+    entity: Option<Entity>,
+}
+
+impl EntityRef {
+    pub fn new(id: StableId, entity: Entity) -> Self {
+        EntityRef {
+            id: id.0,
+            entity: Some(entity),
+        }
+    }
+
+    pub fn id(&self) -> StableId {
+        StableId(self.id)
+    }
+
+    /// The live entity, if it has been resolved and still exists
+    pub fn entity(&self) -> Option<Entity> {
+        self.entity
+    }
+}
+
+impl Serialize for EntityRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(EntityRef {
+            id: u64::deserialize(deserializer)?,
+            entity: None,
+        })
+    }
+}
+
+/// Components that hold `EntityRef`s, to be resolved after loading
+///
+/// Register them with `add_entity_refs` (in `BlueprintAppExt`).
+pub trait EntityRefs: Component {
+    fn entity_refs(&self) -> &[EntityRef];
+    fn entity_refs_mut(&mut self) -> &mut [EntityRef];
+}
+
+type RemapRefsFn = fn(&mut World, &[Entity], &HashMap<StableId, StableId>);
+
+/// All the registered `EntityRefs` types, for rewriting their references
+#[derive(Default)]
+pub struct EntityRefsTypes(Vec<RemapRefsFn>);
+
+impl EntityRefsTypes {
+    pub fn register<T: EntityRefs>(&mut self) {
+        self.0.push(remap_entity_refs::<T>);
+    }
+}
+
+/// Which entity has each `StableId`
+#[derive(Default)]
+pub struct StableIds(HashMap<StableId, Entity>);
+
+impl StableIds {
+    pub fn get(&self, id: StableId) -> Option<Entity> {
+        self.0.get(&id).copied()
+    }
+}
+
+/// Give an ID to scene entities that don't have one, or that have a copy of another's
+pub(super) fn assign_stable_ids(
+    mut commands: Commands,
+    q_new: Query<Entity, (With<SaveSceneMarker>, Without<StableId>)>,
+    q_ids: Query<(Entity, &StableId, ChangeTrackers<StableId>)>,
+) {
+    let mut rng = rand::thread_rng();
+    for e in q_new.iter() {
+        commands.entity(e).insert(StableId::random(&mut rng));
+    }
+
+    if !q_ids.iter().any(|(_, _, tracker)| tracker.is_added()) {
+        return;
+    }
+    // the entities that were already there keep their IDs
+    let mut taken: HashSet<StableId> = q_ids.iter()
+        .filter(|(_, _, tracker)| !tracker.is_added())
+        .map(|(_, id, _)| *id)
+        .collect();
+    for (e, id, _) in q_ids.iter().filter(|(_, _, tracker)| tracker.is_added()) {
+        if !taken.insert(*id) {
+            let new_id = StableId::random(&mut rng);
+            taken.insert(new_id);
+            commands.entity(e).insert(new_id);
+        }
+    }
+}
+
+/// Give a copied group of entities new IDs, and point the references within
+/// the group at the copies
+///
+/// Call right after spawning the copies; `entities` must be all of them.
+pub fn assign_copied_ids(world: &mut World, entities: &[Entity]) {
+    let mut rng = rand::thread_rng();
+    let mut new_ids = HashMap::default();
+    for e in entities {
+        if let Some(mut id) = world.get_mut::<StableId>(*e) {
+            let new_id = StableId::random(&mut rng);
+            new_ids.insert(*id, new_id);
+            *id = new_id;
+        }
+    }
+    let types = world.get_resource::<EntityRefsTypes>()
+        .map(|types| types.0.clone())
+        .unwrap_or_default();
+    for remap in types {
+        remap(world, entities, &new_ids);
+    }
+}
+
+fn remap_entity_refs<T: EntityRefs>(
+    world: &mut World,
+    entities: &[Entity],
+    new_ids: &HashMap<StableId, StableId>,
+) {
+    for e in entities {
+        if let Some(mut refs) = world.get_mut::<T>(*e) {
+            for r in refs.entity_refs_mut() {
+                if let Some(new_id) = new_ids.get(&r.id()) {
+                    // resolved again once the new IDs are indexed
+                    *r = EntityRef {
+                        id: new_id.0,
+                        entity: None,
+                    };
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn index_stable_ids(
+    mut ids: ResMut<StableIds>,
+    removed: RemovedComponents<StableId>,
+    q_changed: Query<(), Changed<StableId>>,
+    q: Query<(Entity, &StableId)>,
+) {
+    if removed.iter().next().is_none() && q_changed.iter().next().is_none() {
+        return;
+    }
+    ids.0 = q.iter().map(|(e, id)| (*id, e)).collect();
+}
+
+/// Point the references of `T` at the live entities
+///
+/// Runs for all of them when entities come and go, or just the changed ones otherwise.
+pub fn resolve_entity_refs<T: EntityRefs>(
+    ids: Res<StableIds>,
+    mut q: Query<(&mut T, ChangeTrackers<T>)>,
+) {
+    for (mut refs, tracker) in q.iter_mut() {
+        if !ids.is_changed() && !tracker.is_changed() {
+            continue;
+        }
+        let stale = refs.entity_refs().iter()
+            .any(|r| r.entity != ids.get(r.id()));
+        if !stale {
+            continue;
+        }
+        for r in refs.entity_refs_mut() {
+            r.entity = ids.get(r.id());
+        }
+    }
+}
@@ -3,12 +3,15 @@ use bevy::{prelude::*, app::AppExit};
 use iyes_bevy_util::{despawn_with_recursive};
 
 use crate::{GameMode, AppState, FuckStages};
+use crate::game::savegame::{self, PendingRestore};
 
 use super::{UiAssets, UiNinepatches, ContentId, UiConfig, Btn, fill_btn, spawn_button};
 
 mod btn {
     use bevy::prelude::*;
 
+    #[derive(Component, Clone, Copy)]
+    pub struct Continue;
     #[derive(Component, Clone, Copy)]
     pub struct EnterGame(pub crate::GameMode);
     #[derive(Component, Clone, Copy)]
@@ -34,8 +37,10 @@ impl Plugin for MainMenuPlugin {
         let update = SystemSet::on_update(AppState::MainMenu);
         let update = btn::ExitApp::register_handler( update);
         let update = btn::EnterGame::register_handler( update);
+        let update = btn::Continue::register_handler( update);
         app.add_system_set(update);
 
+        app.add_system_to_stage(FuckStages::Post, fill_btn::<btn::Continue>);
         app.add_system_to_stage(FuckStages::Post, fill_btn::<btn::EnterGame>);
         app.add_system_to_stage(FuckStages::Post, fill_btn::<btn::ExitApp>);
     }
@@ -96,6 +101,16 @@ fn init_mainmenu(
     //})
     .insert(MainMenuCleanup).id();
 
+    if savegame::savegame_exists() {
+        spawn_button(
+            &mut cmd,
+            uicfg.btn_style.clone(),
+            assets.npimg_button.clone(),
+            nps.npmeta_button.clone(),
+            menu, btn::Continue
+        );
+    }
+
     spawn_button(
         &mut cmd,
         uicfg.btn_style.clone(),
@@ -121,6 +136,34 @@ fn init_mainmenu(
     );
 }
 
+impl Btn for btn::Continue {
+    fn fill_content(&self) -> String {
+        "Continue".into()
+    }
+    fn register_handler(sset: SystemSet) -> SystemSet {
+        fn handler(
+            In(clicked): In<Option<btn::Continue>>,
+            mut state: ResMut<State<AppState>>,
+            mut cmd: Commands,
+        ) {
+            if clicked.is_some() {
+                match savegame::read_savegame() {
+                    Ok(save) => {
+                        state.set(AppState::GameAssetLoading(save.mode)).unwrap();
+                        cmd.insert_resource(save.mode);
+                        cmd.insert_resource(PendingRestore(save));
+                    }
+                    Err(e) => error!("{}", e),
+                }
+            }
+        }
+        sset.with_system(
+            crate::ui::button_connector::<Self>.system()
+                .chain(handler)
+        )
+    }
+}
+
 impl Btn for btn::EnterGame {
     fn fill_content(&self) -> String {
         match self.0 {